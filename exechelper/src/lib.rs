@@ -1,5 +1,5 @@
 use std::process::Command;
-use std::{io::Error, process::Stdio, ffi::OsStr};
+use std::{io::{Error, ErrorKind, Read}, process::Stdio, time::{Duration, Instant}};
 
 pub struct CommandOutput {
     pub stdout: String,
@@ -71,6 +71,62 @@ pub fn execute(exe_and_args: &[&str]) -> Result<CommandOutput, Error> {
     execute_with_env(exe_and_args, &[], &[])
 }
 
+/// same as execute_with_env, but if the process is still running
+/// after the timeout it gets killed and an error of kind TimedOut
+/// is returned.
+pub fn execute_with_env_timeout(
+    exe_and_args: &[&str],
+    keys: &[&str],
+    vals: &[&str],
+    timeout: Duration,
+) -> Result<CommandOutput, Error> {
+    let mut child = spawn_with_env_ex(exe_and_args, keys, vals,
+        Some(Stdio::null()), Some(Stdio::piped()), Some(Stdio::piped()))?;
+
+    // read both pipes on their own threads so that a chatty child
+    // cannot block on a full pipe while we wait for it
+    let mut stdout_pipe = child.stdout.take();
+    let mut stderr_pipe = child.stderr.take();
+    let stdout_reader = std::thread::spawn(move || {
+        let mut buf = vec![];
+        if let Some(pipe) = stdout_pipe.as_mut() {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    });
+    let stderr_reader = std::thread::spawn(move || {
+        let mut buf = vec![];
+        if let Some(pipe) = stderr_pipe.as_mut() {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Error::new(ErrorKind::TimedOut, format!(
+                "{} timed out after {}s and was killed", exe_and_args[0], timeout.as_secs())));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+
+    let out = stdout_reader.join().unwrap_or_default();
+    let err = stderr_reader.join().unwrap_or_default();
+    Ok(
+        CommandOutput {
+            stdout: String::from_utf8_lossy(&out).into_owned(),
+            stderr: String::from_utf8_lossy(&err).into_owned(),
+            status: status.code().unwrap_or(1),
+        }
+    )
+}
+
 /// optionally pass in what kind of stdio config
 /// you want to use for each stream. passing None
 /// will use whatever the default is.
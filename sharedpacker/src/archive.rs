@@ -0,0 +1,180 @@
+use std::{path::Path, process::Stdio};
+use serde::{Serialize, Deserialize};
+use crate::Error;
+use crate::tools::{execute_checked, tool_error};
+use crate::wrapper::shell_escape_double_quoted;
+
+/// what the output is written as
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// a folder
+    #[default]
+    Dir,
+    /// a squashfs image of the folder, made with mksquashfs
+    Squashfs,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dir" => Ok(OutputFormat::Dir),
+            "squashfs" => Ok(OutputFormat::Squashfs),
+            other => Err(format!("unknown output format {:?}, expected dir or squashfs", other)),
+        }
+    }
+}
+
+/// makes sure mksquashfs can be run before we start copying anything
+pub fn check_mksquashfs_installed() -> Result<(), Error> {
+    let output = execute_checked(&["mksquashfs", "-version"])
+        .map_err(|e| Error::MissingTool(format!(
+            "--format squashfs needs mksquashfs, which is in the squashfs-tools package. is it installed?\n{}", e)))?;
+    if output.status != 0 {
+        return Err(Error::MissingTool(format!("--format squashfs was given but mksquashfs -version failed\n{}", output.stderr)));
+    }
+    Ok(())
+}
+
+/// uses mksquashfs to write the contents of the archive folder into a squashfs image, which
+/// replaces any image already at image_path. the files keep their modes, so the executables
+/// stay executable. with an mtime, every file gets it and is owned by root, so the image is reproducible
+pub fn make_squashfs(archive_path: &Path, image_path: &Path, mtime: Option<u64>) -> Result<(), Error> {
+    let archive_str = archive_path.to_string_lossy();
+    let image_str = image_path.to_string_lossy();
+    let mtime_str = mtime.map(|m| m.to_string()).unwrap_or_default();
+    let mut exec_args = vec!["mksquashfs", &archive_str, &image_str, "-noappend", "-no-progress"];
+    if mtime.is_some() {
+        exec_args.extend(["-all-time", &mtime_str, "-mkfs-time", &mtime_str, "-all-root", "-no-xattrs"]);
+    }
+    let output = execute_checked(&exec_args)?;
+    if output.status != 0 {
+        return Err(Error::Io(format!("Failed to write {:?}\n{}", image_path, output.stderr)));
+    }
+    Ok(())
+}
+
+/// the tar flags that make a tarball only depend on the files' names and contents:
+/// sorted entries, no owner, and every mtime set to the given one. gzip already
+/// leaves the time out of its header when it compresses tar's stdout
+fn reproducible_tar_args(mtime: Option<u64>) -> Vec<String> {
+    match mtime {
+        Some(mtime) => vec![
+            "--sort=name".into(), "--owner=0".into(), "--group=0".into(),
+            "--numeric-owner".into(), format!("--mtime=@{}", mtime),
+        ],
+        None => vec![],
+    }
+}
+
+/// uses tar to write the archive folder into a gzipped tarball. the tarball
+/// contains the folder itself, so it extracts to a folder of the same name.
+/// with an mtime, the tarball is reproducible, see reproducible_tar_args
+pub fn make_targz(archive_path: &Path, tarball_path: &Path, mtime: Option<u64>) -> Result<(), Error> {
+    let dirname = archive_path.file_name()
+        .ok_or_else(|| Error::Other(format!("Failed to find folder name of {:?}", archive_path)))?
+        .to_string_lossy().to_string();
+    let parent = match archive_path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_string_lossy().to_string(),
+        _ => ".".into(),
+    };
+    let tarball_str = tarball_path.to_string_lossy();
+    let extra_args = reproducible_tar_args(mtime);
+    let mut exec_args = vec!["tar", "-czf", &tarball_str];
+    exec_args.extend(extra_args.iter().map(|a| a.as_str()));
+    exec_args.extend(["-C", &parent, &dirname]);
+    let output = execute_checked(&exec_args)?;
+    if output.status != 0 {
+        return Err(Error::Io(format!("Failed to write {:?}\n{}", tarball_path, output.stderr)));
+    }
+    Ok(())
+}
+
+/// like make_targz, but streams an uncompressed tarball to our own stdout
+pub fn write_tar_to_stdout(archive_path: &Path, mtime: Option<u64>) -> Result<(), Error> {
+    let dirname = archive_path.file_name()
+        .ok_or_else(|| Error::Other(format!("Failed to find folder name of {:?}", archive_path)))?
+        .to_string_lossy().to_string();
+    let parent = match archive_path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_string_lossy().to_string(),
+        _ => ".".into(),
+    };
+    let extra_args = reproducible_tar_args(mtime);
+    let mut exec_args = vec!["tar", "-cf", "-"];
+    exec_args.extend(extra_args.iter().map(|a| a.as_str()));
+    exec_args.extend(["-C", &parent, &dirname]);
+    let status = exechelper::spawn_with_env_ex(
+        &exec_args, &[], &[], Some(Stdio::null()), Some(Stdio::inherit()), Some(Stdio::inherit()))
+        .and_then(|mut child| child.wait())
+        .map_err(|e| tool_error("tar", e))?;
+    if !status.success() {
+        return Err(Error::Io(format!("Failed to stream {:?} to stdout as a tarball", archive_path)));
+    }
+    Ok(())
+}
+
+/// the script that goes in front of the payload of a self extracting archive.
+/// payload_offset is the length of this header in bytes, so that tail
+/// only passes the tarball that follows it to tar
+pub fn make_self_extract_header(
+    dirname: &str,
+    execname: &str,
+    payload_offset: usize,
+) -> String {
+    let exec_path = format!("$EXTRACT_DIR/{}/{}", shell_escape_double_quoted(dirname), shell_escape_double_quoted(execname));
+    format!("#!/usr/bin/env bash\n\
+        # self extracting archive made by sharedpacker\n\
+        set -e\n\
+        PAYLOAD_OFFSET={}\n\
+        EXTRACT_DIR=\"$(mktemp -d)\"\n\
+        trap 'rm -rf \"$EXTRACT_DIR\"' EXIT\n\
+        tail -c +$((PAYLOAD_OFFSET + 1)) \"$0\" | tar -xzf - -C \"$EXTRACT_DIR\"\n\
+        set +e\n\
+        \"{}\" \"$@\"\n\
+        exit $?\n", payload_offset, exec_path)
+}
+
+/// packs the archive folder with make_targz and writes a script to script_path
+/// that extracts it to a temporary directory and runs the wrapper of execname in it.
+/// the temporary directory is removed once the wrapper exits
+pub fn make_self_extracting(
+    archive_path: &Path,
+    execname: &str,
+    script_path: &Path,
+    mtime: Option<u64>,
+) -> Result<(), Error> {
+    let dirname = archive_path.file_name()
+        .ok_or_else(|| Error::Other(format!("Failed to find folder name of {:?}", archive_path)))?
+        .to_string_lossy().to_string();
+    let tarball_path = script_path.with_extension("tar.gz.tmp");
+    make_targz(archive_path, &tarball_path, mtime)?;
+    let payload = std::fs::read(&tarball_path)
+        .map_err(|e| Error::Io(format!("Failed to read {:?}\n{}", tarball_path, e)));
+    let _ = std::fs::remove_file(&tarball_path);
+    let payload = payload?;
+
+    // the offset is part of the header, so keep regenerating it
+    // until the length it states is its own length
+    let mut offset = 0;
+    let header = loop {
+        let header = make_self_extract_header(&dirname, execname, offset);
+        if header.len() == offset {
+            break header;
+        }
+        offset = header.len();
+    };
+
+    let mut contents = header.into_bytes();
+    contents.extend_from_slice(&payload);
+    std::fs::write(script_path, contents)
+        .map_err(|e| Error::Io(format!("Failed to write {:?}\n{}", script_path, e)))?;
+    let script_path_str = script_path.to_string_lossy();
+    let exec_args = ["chmod", "+x", &script_path_str];
+    let out = execute_checked(&exec_args)?;
+    if out.status != 0 {
+        return Err(Error::Io(out.stderr));
+    }
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use std::{path::{Component, Path, PathBuf}, collections::VecDeque};
+use crate::Error;
+use crate::deps::get_needed_libs;
+use crate::elftool::ElfTool;
+use crate::files::{list_files_relative, is_elf_file};
+use crate::filter::is_excluded;
+
+/// runs get_needed_libs on every ELF file in the archive and makes sure
+/// each needed lib (other than the loader, and the libs that were excluded
+/// on purpose) exists as a file next to it
+pub fn check_closure(
+    tools: &dyn ElfTool,
+    archive_path: &Path,
+    loader_names: &[String],
+    exclude: &[String],
+) -> Result<(), Error> {
+    let mut files = vec![];
+    list_files_relative(archive_path, archive_path, &mut files)?;
+    files.sort();
+
+    let mut missing = vec![];
+    for file in files {
+        let file_path = archive_path.join(&file);
+        if !is_elf_file(&file_path) {
+            continue;
+        }
+        for needed in get_needed_libs(tools, &file_path, loader_names)? {
+            if is_excluded(&needed, exclude) {
+                continue;
+            }
+            // a file in a subdirectory, like a gconv module, can also find its libs next to itself
+            if !archive_path.join(&needed).is_file() && !file_path.with_file_name(&needed).is_file() {
+                missing.push(format!("{} (needed by {})", needed, file.to_string_lossy()));
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(Error::MissingDependency(format!("Bundle is not self-contained, missing:\n  {}", missing.join("\n  "))));
+    }
+    Ok(())
+}
+
+/// what check_bundle found out about one ELF file in the bundle
+struct BundledElf {
+    name: String,
+    interpreter: Option<String>,
+    rpath: Option<String>,
+    needed: Vec<String>,
+}
+
+/// whether an rpath is one that packing sets: its first entry is . or relative to $ORIGIN, eg:
+/// $ORIGIN/../lib with mirror paths. anything after it can come from the base rpath, or
+/// be the rpath the file had before, with append rpath
+fn is_packed_rpath(rpath: &str) -> bool {
+    let first = rpath.split(':').next().unwrap_or_default();
+    first == "." || first == "$ORIGIN" || first.starts_with("$ORIGIN/")
+}
+
+/// validates a bundle that was already built: every ELF file with an interpreter has to
+/// point it at ./<loader> in the bundle, every patched file has to have the expected
+/// rpath, and every needed lib has to be in the bundle, where the loader would find it
+/// through the file's rpath. without an expected_rpath, any rpath that packing sets is
+/// accepted, see is_packed_rpath. returns every problem found, rather than stopping at the first one
+pub fn check_bundle(
+    tools: &dyn ElfTool,
+    archive_path: &Path,
+    expected_rpath: Option<&str>,
+) -> Result<Vec<String>, Error> {
+    // like the search dirs, so that starts_with can compare them
+    let archive_path = &normalize_path(archive_path);
+    let mut files = vec![];
+    list_files_relative(archive_path, archive_path, &mut files)?;
+    files.sort();
+
+    let mut elfs = vec![];
+    for file in files {
+        let file_path = archive_path.join(&file);
+        if !is_elf_file(&file_path) {
+            continue;
+        }
+        elfs.push(BundledElf {
+            name: file.to_string_lossy().to_string(),
+            interpreter: tools.interpreter(&file_path)?,
+            rpath: tools.rpath(&file_path)?,
+            // the loader is needed too, eg: by libc, and it has to be in the bundle like any lib
+            needed: get_needed_libs(tools, &file_path, &[])?,
+        });
+    }
+
+    // the loaders are the files that the interpreters point at. they are copied as is
+    let loaders: Vec<String> = elfs.iter()
+        .filter_map(|e| e.interpreter.as_deref())
+        .map(|i| i.rsplit('/').next().unwrap_or(i).to_string())
+        .collect();
+
+    let mut problems = vec![];
+    for elf in &elfs {
+        if loaders.contains(&elf.name) {
+            continue;
+        }
+        if let Some(interpreter) = &elf.interpreter {
+            let loader_name = interpreter.strip_prefix("./").filter(|n| !n.contains('/'));
+            match loader_name {
+                Some(name) if archive_path.join(name).is_file() => {}
+                Some(name) => problems.push(format!("{}: its interpreter {} is not in the bundle", elf.name, name)),
+                None => problems.push(format!("{}: its interpreter is {}, instead of a loader in the bundle", elf.name, interpreter)),
+            }
+        }
+        // a statically linked file has no rpath or needed libs to check
+        let rpath = match &elf.rpath {
+            Some(r) => r,
+            None => continue,
+        };
+        let rpath_ok = match expected_rpath {
+            Some(expected) => rpath == expected,
+            None => is_packed_rpath(rpath),
+        };
+        if !rpath_ok {
+            problems.push(format!("{}: its rpath is {:?}", elf.name, rpath));
+        }
+        let dirs = bundle_search_dirs(archive_path, &archive_path.join(&elf.name), rpath);
+        for needed in &elf.needed {
+            let in_bundle = dirs.iter().map(|dir| dir.join(needed))
+                .any(|path| path.starts_with(archive_path) && path.is_file());
+            if !in_bundle {
+                problems.push(format!("{}: needs {}, which is not in the bundle", elf.name, needed));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+/// asks the bundled loader whether it can load each of the executables, with
+/// ld.so --verify, which checks them without running any of their code.
+/// returns false without checking anything if the loader has no --verify,
+/// eg: musl's. the executables are given by their file names in the archive
+pub fn verify_runnable(
+    tools: &dyn ElfTool,
+    archive_path: &Path,
+    loader_name: &str,
+    exec_files: &[String],
+) -> Result<bool, Error> {
+    let loader_path = archive_path.join(loader_name);
+    let loader_str = loader_path.to_string_lossy();
+    let help = tools.run(&[&loader_str, "--help"])?;
+    if !help.stdout.contains("--verify") {
+        return Ok(false);
+    }
+    let mut failures = vec![];
+    for exec_file in exec_files {
+        let exec_str = archive_path.join(exec_file).to_string_lossy().to_string();
+        let out = tools.run(&[&loader_str, "--verify", &exec_str])?;
+        if out.status != 0 {
+            failures.push(format!("{} (exit code {}) {}", exec_file, out.status, out.stderr.trim()));
+        }
+    }
+    if !failures.is_empty() {
+        return Err(Error::Other(format!(
+            "The bundled loader {} cannot load:\n  {}", loader_name, failures.join("\n  "))));
+    }
+    Ok(true)
+}
+
+/// the directories that the loader searches for the needed libs of a file in the archive:
+/// its rpath, with $ORIGIN as the file's own directory and a relative entry like . taken
+/// from the top of the archive, where the wrapper points LD_LIBRARY_PATH too
+fn bundle_search_dirs(archive_path: &Path, file_path: &Path, rpath: &str) -> Vec<PathBuf> {
+    let origin = file_path.parent().unwrap_or(archive_path);
+    let mut dirs: Vec<PathBuf> = rpath.split(':').filter(|entry| !entry.is_empty()).map(|entry| {
+        match entry.strip_prefix("${ORIGIN}").or_else(|| entry.strip_prefix("$ORIGIN")) {
+            Some(rest) => origin.join(rest.trim_start_matches('/')),
+            None if entry.starts_with('/') => PathBuf::from(entry),
+            None => archive_path.join(entry),
+        }
+    }).collect();
+    dirs.push(archive_path.to_path_buf());
+    dirs.iter().map(|dir| normalize_path(dir)).collect()
+}
+
+/// the path without any . components, and with each .. taking away the component
+/// before it, eg: out/./usr/lib/../lib to out/usr/lib, so that starts_with can tell
+/// whether it is in the archive
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.pop() => {}
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// like ldd on an executable in the archive, but found by looking through the archive rather than
+/// by running it: every lib in its closure, in the order they are first needed, with the file that
+/// each resolves to, or None. the libs of a file outside the archive are not followed. the loader is
+/// listed last, as what the executable's interpreter points at. exec_file is its name in the archive
+pub fn resolve_in_bundle(
+    tools: &dyn ElfTool,
+    archive_path: &Path,
+    exec_file: &str,
+    loader_names: &[String],
+) -> Result<Vec<(String, Option<PathBuf>)>, Error> {
+    let archive_path = &normalize_path(archive_path);
+    let exec_path = archive_path.join(exec_file);
+    let mut resolved: Vec<(String, Option<PathBuf>)> = vec![];
+    let mut queue = VecDeque::from([exec_path.clone()]);
+    while let Some(file_path) = queue.pop_front() {
+        let dirs = bundle_search_dirs(archive_path, &file_path, &tools.rpath(&file_path)?.unwrap_or_default());
+        for needed in get_needed_libs(tools, &file_path, loader_names)? {
+            if resolved.iter().any(|(name, _)| *name == needed) {
+                continue;
+            }
+            let found = dirs.iter().map(|dir| dir.join(&needed)).find(|path| path.is_file());
+            if let Some(found) = found.as_ref().filter(|path| path.starts_with(archive_path)) {
+                queue.push_back(found.clone());
+            }
+            resolved.push((needed, found));
+        }
+    }
+    if let Some(interpreter) = tools.interpreter(&exec_path)? {
+        let path = if interpreter.starts_with('/') {
+            PathBuf::from(&interpreter)
+        } else { normalize_path(&archive_path.join(&interpreter)) };
+        resolved.push((interpreter, Some(path).filter(|path| path.is_file())));
+    }
+    Ok(resolved)
+}
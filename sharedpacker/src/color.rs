@@ -0,0 +1,33 @@
+use std::{io::IsTerminal, sync::OnceLock};
+
+/// colors are only used when stderr is a terminal and NO_COLOR is not set.
+/// see https://no-color.org
+pub fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && std::io::stderr().is_terminal()
+    })
+}
+
+fn paint(code: &str, s: &str) -> String {
+    if enabled() {
+        format!("\x1b[{}m{}\x1b[0m", code, s)
+    } else {
+        s.to_string()
+    }
+}
+
+/// for the names of libs and executables
+pub fn name(s: &str) -> String {
+    paint("1;36", s)
+}
+
+/// for file paths
+pub fn path(s: &str) -> String {
+    paint("33", s)
+}
+
+/// for punctuation like the => between a lib and its path
+pub fn dim(s: &str) -> String {
+    paint("2", s)
+}
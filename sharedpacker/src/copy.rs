@@ -0,0 +1,704 @@
+use std::{path::{Component, Path, PathBuf}, collections::HashMap, time::{Duration, Instant}, sync::{Condvar, Mutex, atomic::{AtomicU64, AtomicUsize, Ordering}}};
+use serde::{Serialize, Deserialize};
+use crate::Error;
+use crate::tools::execute_checked;
+use crate::elftool::ElfTool;
+use crate::ldd::SharedLib;
+use crate::deps::DependencyNode;
+use crate::patch::{LoaderPatch, PatchOps, patch_loader, verify_patch_ops};
+use crate::wrapper::{WrapperOptions, make_shell_script_wrapper};
+use crate::files::{sha256_file, is_elf_file, warn_unusual_permissions, set_mode, create_output_dir, RemoveOnDrop};
+use crate::install::sibling_path;
+use crate::events;
+use crate::filter::LibFilter;
+use indicatif::{ProgressBar, ProgressStyle};
+
+pub fn cleanup_if_err(archive_path: &PathBuf) {
+    let _ = std::fs::remove_dir_all(archive_path);
+}
+
+/// name of the file inside the output where --incremental records
+/// the hash of the source file that each bundled file was made from
+pub const STATE_FILE_NAME: &str = ".sharedpacker-state";
+
+/// parses a state file of "<hash>  <filename>" lines into a map
+/// of filename to hash. a missing state file is an empty map
+pub fn read_state_file(archive_path: &Path) -> HashMap<String, String> {
+    let mut state = HashMap::new();
+    let contents = std::fs::read_to_string(archive_path.join(STATE_FILE_NAME)).unwrap_or_default();
+    for line in contents.lines() {
+        if let Some((hash, name)) = line.split_once("  ") {
+            state.insert(name.to_string(), hash.to_string());
+        }
+    }
+    state
+}
+
+pub fn write_state_file(
+    archive_path: &Path,
+    state: &HashMap<String, String>,
+) -> Result<(), Error> {
+    let mut names: Vec<&String> = state.keys().collect();
+    names.sort();
+    let mut out = String::new();
+    for name in names {
+        out.push_str(&format!("{}  {}\n", state[name], name));
+    }
+    let state_path = archive_path.join(STATE_FILE_NAME);
+    std::fs::write(&state_path, out)
+        .map_err(|e| Error::Io(format!("Failed to write {:?}\n{}", state_path, e)))
+}
+
+/// how a file is patched after it is copied
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PatchStrategy {
+    /// copy the file into the output, and patch it there
+    #[default]
+    InPlace,
+    /// copy the file to a scratch directory next to the output, patch it there, and only
+    /// then move it into the output, so the output never holds a partially patched file
+    TempThenMove,
+}
+
+impl std::str::FromStr for PatchStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in-place" => Ok(PatchStrategy::InPlace),
+            "temp-then-move" => Ok(PatchStrategy::TempThenMove),
+            other => Err(format!("unknown patch strategy {:?}, expected in-place or temp-then-move", other)),
+        }
+    }
+}
+
+/// a hidden directory next to the archive, so moving out of it never crosses a filesystem
+fn scratch_dir_next_to(archive_path: &Path) -> Result<RemoveOnDrop, Error> {
+    let path = sibling_path(archive_path, &format!("patching-{}", std::process::id()))?;
+    std::fs::create_dir_all(&path)
+        .map_err(|e| Error::Io(format!("Failed to create scratch directory {:?}\n{}", path, e)))?;
+    Ok(RemoveOnDrop::new(path))
+}
+
+/// options that control how copy_dependencies_to_output_folder lays out the archive
+#[derive(Debug, Default)]
+pub struct CopyOptions {
+    pub make_wrapper: bool,
+    /// skip the copy+patch of any file whose source hash matches
+    /// what was recorded in the state file by a previous run
+    pub incremental: bool,
+    /// add to an existing archive without overwriting files that are already in it
+    pub merge: bool,
+    /// dont copy the executables, only their libs and the loader
+    pub libs_only: bool,
+    /// copy the loader under the name of the real file its path resolves to, and
+    /// make the name that the executables reference a symlink to it
+    pub loader_symlink: bool,
+    /// instead of moving the executable aside to .execname-original, leave it in place and
+    /// write the wrapper next to it, under the executable's name with this suffix
+    pub wrapper_suffix: Option<String>,
+    /// set the interpreter of the executables to the loader in this directory, which is where the
+    /// archive is going to be installed, instead of to ./loader. the rpath then becomes $ORIGIN,
+    /// so they can be run directly from any working directory
+    pub interpreter_dir: Option<PathBuf>,
+    /// leave the interpreter of the executables as it is. the loader is still copied, and
+    /// the wrapper runs the executable with it, but run directly it uses the system loader
+    pub keep_interpreter: bool,
+    /// what the wrapper scripts set up before launching the executables
+    pub wrapper: WrapperOptions,
+    /// keep the existing rpath of each copied file after $ORIGIN instead of replacing it
+    pub append_rpath: bool,
+    /// make the executables ignore the default system lib dirs, so that a lib
+    /// missing from the archive fails to load instead of coming from the host
+    pub no_default_lib: bool,
+    /// read back the interpreter and rpath of every patched file to make sure patchelf set them
+    pub verify_patch: bool,
+    pub patch_strategy: PatchStrategy,
+    /// when a file fails to copy or patch, record it in the report's failures and move on to the next one
+    pub best_effort: bool,
+    /// put each lib under the output at the directory it was copied from, eg: out/usr/lib/libc.so.6,
+    /// instead of next to the executables. the rpaths point at all of those directories
+    pub mirror_paths: bool,
+    /// fail once the files copied by this run add up to more than this many bytes
+    pub max_size: Option<u64>,
+    /// say which files were already patched, and so were left as they are
+    pub verbose: bool,
+    /// show how many files have been copied so far. this draws nothing
+    /// unless stderr is a terminal
+    pub progress: bool,
+    /// the hashes of the libs in a base image, by name. a lib whose source has the
+    /// same name and hash is left out, since the base already provides it
+    pub base_libs: HashMap<String, String>,
+    /// added to the end of every rpath, so the libs that were left out for
+    /// being in the base are found where the base puts them
+    pub base_rpath: Option<String>,
+    /// libs that this returns false for are not copied. it is not asked about the executables or the loader
+    pub filter: Option<LibFilter>,
+    /// how many files to copy at once. 0 is treated as 1
+    pub copy_jobs: usize,
+    /// how many patchelf runs (with their verify steps) to have going at once. 0 is treated as 1
+    pub patch_jobs: usize,
+    /// the mode to give the executables (and their wrappers) in the archive,
+    /// instead of whatever mode the copies kept from their sources
+    pub exec_mode: Option<u32>,
+}
+
+impl CopyOptions {
+    /// the name of the wrapper script that launches execname
+    pub fn wrapper_name(&self, execname: &str) -> String {
+        match &self.wrapper_suffix {
+            Some(suffix) => format!("{}{}", execname, suffix),
+            None => execname.into(),
+        }
+    }
+
+    /// the name the executable itself is copied to
+    pub fn wrapped_exec_name(&self, execname: &str) -> String {
+        if self.make_wrapper && self.wrapper_suffix.is_none() {
+            format!(".{}-original", execname)
+        } else { execname.into() }
+    }
+}
+
+/// the basename of the file that the loader's path resolves to after following symlinks
+pub fn loader_real_name(loader: &SharedLib) -> Result<String, Error> {
+    let real_path = std::fs::canonicalize(&loader.path)
+        .map_err(|e| Error::Io(format!("Failed to resolve loader path {:?}\n{}", loader.path, e)))?;
+    match real_path.file_name() {
+        Some(name) => Ok(name.to_string_lossy().to_string()),
+        None => Err(Error::Other(format!("Failed to find file name for {:?}", real_path))),
+    }
+}
+
+/// what copy_dependencies_to_output_folder should do about one file
+#[derive(Debug, PartialEq)]
+pub enum CopyAction {
+    Copy,
+    /// the source is unchanged since the run that produced the existing copy,
+    /// or in merge mode, the existing copy is identical to it
+    Unchanged,
+    /// in merge mode, a different file of the same name is already in the archive
+    Collision,
+}
+
+/// compares the source file's hash with the one recorded in the state file
+/// for the copy that is already in the archive. in merge mode, a copy without a
+/// recorded hash, eg: in an archive packed without merge, is compared with the
+/// source itself. records the hash of anything that is going to be copied, or
+/// that is found to be identical. without a state, everything gets copied
+pub fn decide_copy_action(
+    state: &mut Option<HashMap<String, String>>,
+    state_key: &str,
+    source_path: &Path,
+    existing_path: &Path,
+    merge: bool,
+) -> Result<CopyAction, Error> {
+    let state = match state {
+        Some(s) => s,
+        None => return Ok(CopyAction::Copy),
+    };
+    let hash = sha256_file(source_path)?;
+    if state.get(state_key) == Some(&hash) && is_elf_file(existing_path) {
+        return Ok(CopyAction::Unchanged);
+    }
+    if merge && existing_path.exists() {
+        if !state.contains_key(state_key) && sha256_file(existing_path)? == hash {
+            state.insert(state_key.into(), hash);
+            return Ok(CopyAction::Unchanged);
+        }
+        return Ok(CopyAction::Collision);
+    }
+    state.insert(state_key.into(), hash);
+    Ok(CopyAction::Copy)
+}
+
+/// things worth telling the user about after copying
+#[derive(Debug, Default)]
+pub struct CopyReport {
+    /// files that were already in the archive with different contents, and so were left alone
+    pub collisions: Vec<String>,
+    /// how many files were patched. each one takes a single patchelf run to write
+    pub patched_files: usize,
+    /// how many patchelf operations those runs applied in total
+    pub patch_operations: usize,
+    /// the total size of the files copied by this run, after patching
+    pub copied_bytes: u64,
+    /// with best_effort, the names of the files that failed to copy or patch, and so are not in the archive
+    pub failures: Vec<String>,
+    /// the libs that were left out because the base_libs already has them
+    pub in_base: Vec<String>,
+    /// the libs that were left out because the filter said so
+    pub filtered: Vec<String>,
+    /// the time spent copying files, added up over every file. with copy_jobs
+    /// above 1 this can be more than the wall clock time it took
+    pub copy_time: Duration,
+    /// the time spent patching files, including verifying them, added up like copy_time
+    pub patch_time: Duration,
+    /// where the executables and libs copied by this run are, relative to the archive. this
+    /// does not include the loader. the executables are listed under their wrapped_exec_name
+    pub copied_files: Vec<PathBuf>,
+}
+
+/// where a lib goes under the archive with mirror_paths: the directory of its source path
+fn mirrored_dir(dep: &DependencyNode) -> PathBuf {
+    dep.path.parent()
+        .map(|dir| dir.components().filter(|c| matches!(c, Component::Normal(_))).collect())
+        .unwrap_or_default()
+}
+
+/// an rpath for a file in the archive directory file_dir, made of $ORIGIN relative
+/// entries for each of the lib_dirs. both are relative to the archive
+pub fn mirrored_rpath(file_dir: &Path, lib_dirs: &[PathBuf]) -> String {
+    let up = "../".repeat(file_dir.components().count());
+    let entries: Vec<String> = lib_dirs.iter().map(|lib_dir| {
+        let relative = format!("{}{}", up, lib_dir.display());
+        match relative.trim_end_matches('/') {
+            "" => "$ORIGIN".to_string(),
+            relative => format!("$ORIGIN/{}", relative),
+        }
+    }).collect();
+    entries.join(":")
+}
+
+/// adds the size of a file that was just copied to the running total of the files copied
+/// by this run, and fails if that takes the total over the max_size
+fn add_copied_size(
+    copied_bytes: &AtomicU64,
+    copied_path: &Path,
+    max_size: Option<u64>,
+) -> Result<(), Error> {
+    let size = std::fs::metadata(copied_path)
+        .map_err(|e| Error::Io(format!("Failed to read size of {:?}\n{}", copied_path, e)))?
+        .len();
+    let total = copied_bytes.fetch_add(size, Ordering::SeqCst) + size;
+    if let Some(max) = max_size {
+        if total > max {
+            return Err(Error::Other(format!(
+                "Bundle grew to {} bytes after copying {:?} ({} bytes), which is over the --max-size of {} bytes",
+                total, copied_path, size, max)));
+        }
+    }
+    Ok(())
+}
+
+/// a file that copy_dependencies_to_output_folder is going to copy and patch
+struct PendingCopy<'a> {
+    dep: &'a DependencyNode,
+    filename: String,
+    is_exec: bool,
+    output_path: PathBuf,
+    /// where the file is patched, which is the output_path unless patching in a scratch dir
+    work_path: PathBuf,
+    lib_rpath: Option<String>,
+}
+
+/// a limit on how many threads can be doing one kind of work at once
+struct Slots {
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// gives its slot back when dropped
+struct Slot<'a>(&'a Slots);
+
+impl Slots {
+    fn new(count: usize) -> Slots {
+        Slots { free: Mutex::new(count.max(1)), freed: Condvar::new() }
+    }
+
+    /// waits until a slot is free
+    fn take(&self) -> Slot<'_> {
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        while *free == 0 {
+            free = self.freed.wait(free).unwrap_or_else(|e| e.into_inner());
+        }
+        *free -= 1;
+        Slot(self)
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        *self.0.free.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// runs work on every item with up to threads threads, and returns the
+/// results in the order of the items. with one thread, nothing is spawned
+fn run_in_parallel<T: Sync, R: Send>(
+    items: &[T],
+    threads: usize,
+    work: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    if threads <= 1 {
+        return items.iter().map(work).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..threads.min(items.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let item = match items.get(index) {
+                    Some(item) => item,
+                    None => break,
+                };
+                let result = work(item);
+                results.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(result);
+            });
+        }
+    });
+    results.into_inner().unwrap_or_else(|e| e.into_inner()).into_iter()
+        .map(|r| r.expect("every item is worked on before the threads exit"))
+        .collect()
+}
+
+pub fn copy_dependencies_to_output_folder(
+    tools: &dyn ElfTool,
+    archive_path: &Path,
+    dependencies: &Vec<DependencyNode>,
+    loader: &SharedLib,
+    execnames: &[String],
+    opts: &CopyOptions,
+) -> Result<CopyReport, Error> {
+    create_output_dir(archive_path)?;
+    let mut state = if opts.incremental || opts.merge { Some(read_state_file(archive_path)) } else { None };
+    let mut report = CopyReport::default();
+    let mut unchanged_execs = vec![];
+    let mut colliding_execs = vec![];
+    let mut failed_execs = vec![];
+    let progress = if opts.progress {
+        ProgressBar::new(dependencies.len() as u64)
+    } else { ProgressBar::hidden() };
+    progress.set_style(ProgressStyle::with_template("{bar:40} {pos}/{len} {wide_msg}")
+        .map_err(|e| Error::Other(e.to_string()))?);
+    let scratch_dir = match opts.patch_strategy {
+        PatchStrategy::TempThenMove => Some(scratch_dir_next_to(archive_path)?),
+        PatchStrategy::InPlace => None,
+    };
+
+    let mut lib_dirs: Vec<PathBuf> = vec![];
+    if opts.mirror_paths {
+        // the libs that the filter leaves out are not in the archive to point at
+        let bundled_libs = dependencies.iter()
+            .filter(|d| !execnames.contains(&d.name) && opts.filter.as_ref().is_none_or(|filter| filter.keeps(d)));
+        for dep in bundled_libs {
+            let dir = mirrored_dir(dep);
+            if !lib_dirs.contains(&dir) {
+                lib_dirs.push(dir);
+            }
+        }
+    }
+
+    // the bundled loader, either relative to the working directory or where the archive will be installed
+    let interpreter = match &opts.interpreter_dir {
+        Some(dir) => dir.join(&loader.name).to_string_lossy().to_string(),
+        None => format!("./{}", loader.name),
+    };
+
+    // first decide what to do about each file, which has to go in order
+    // since it reads and updates the state
+    let mut pending = vec![];
+    // which file goes to each path in the archive, to catch two of them going to the same one
+    let mut output_sources: HashMap<PathBuf, &Path> = HashMap::new();
+    for dep in dependencies {
+        let dep_path = &dep.path;
+        // everything is copied under the name it is needed by, or for the executables the
+        // name they were given. the source path is canonical, so its file name might differ
+        let is_exec = execnames.contains(&dep.name);
+        let filename = dep.name.clone();
+        let file_dir = if opts.mirror_paths && !is_exec { mirrored_dir(dep) } else { PathBuf::new() };
+        let output_path = archive_path.join(&file_dir).join(&filename);
+        let lib_rpath = if opts.mirror_paths {
+            Some(mirrored_rpath(&file_dir, &lib_dirs))
+        } else if opts.interpreter_dir.is_some() {
+            // . would only find the libs when run from the archive, which the absolute interpreter is meant to avoid
+            Some("$ORIGIN".to_string())
+        } else { None };
+        let lib_rpath = match &opts.base_rpath {
+            Some(base_rpath) => {
+                let own = lib_rpath.as_deref().unwrap_or(if opts.append_rpath { "$ORIGIN" } else { "." });
+                Some(format!("{}:{}", own, base_rpath))
+            }
+            None => lib_rpath,
+        };
+
+        // when wrapping, the patched executable usually lives at .execname-original
+        if is_exec && opts.libs_only {
+            progress.inc(1);
+            continue;
+        }
+        // eg: an executable named like one of its libs. only one of them would end up in the archive
+        for name in std::iter::once(&filename).chain(&dep.aliases) {
+            let path = archive_path.join(&file_dir).join(name);
+            if let Some(other) = output_sources.insert(path.clone(), dep_path) {
+                return Err(Error::Other(format!(
+                    "Both {:?} and {:?} would be copied to {:?}. rename the executable or exclude one of them",
+                    other, dep_path, path)));
+            }
+        }
+        if !is_exec && opts.filter.as_ref().is_some_and(|filter| !filter.keeps(dep)) {
+            progress.inc(1);
+            report.filtered.push(filename);
+            continue;
+        }
+        if let Some(base_hash) = opts.base_libs.get(&filename).filter(|_| !is_exec) {
+            if *base_hash == sha256_file(dep_path)? {
+                progress.inc(1);
+                report.in_base.push(filename);
+                continue;
+            }
+        }
+        let existing_path = if is_exec {
+            archive_path.join(opts.wrapped_exec_name(&filename))
+        } else { output_path.clone() };
+        match decide_copy_action(&mut state, &filename, dep_path, &existing_path, opts.merge)? {
+            CopyAction::Copy => {}
+            CopyAction::Unchanged => {
+                progress.inc(1);
+                if is_exec {
+                    unchanged_execs.push(filename);
+                }
+                continue;
+            }
+            CopyAction::Collision => {
+                progress.inc(1);
+                report.collisions.push(format!("{} (from {:?})", filename, dep_path));
+                if is_exec {
+                    colliding_execs.push(filename);
+                }
+                continue;
+            }
+        }
+
+        // at the same path under the scratch dir as in the archive, since with mirror
+        // paths two files of the same name can be copied at once from different dirs
+        let work_path = match &scratch_dir {
+            Some(dir) => {
+                let work_dir = dir.path().join(&file_dir);
+                std::fs::create_dir_all(&work_dir)
+                    .map_err(|e| Error::Io(format!("Failed to create scratch directory {:?}\n{}", work_dir, e)))?;
+                work_dir.join(&filename)
+            }
+            None => output_path.clone(),
+        };
+        pending.push(PendingCopy { dep, filename, is_exec, output_path, work_path, lib_rpath });
+    }
+
+    // then copy and patch them, with up to copy_jobs copies and
+    // up to patch_jobs patchelf runs going at once
+    let copy_slots = Slots::new(opts.copy_jobs);
+    let patch_slots = Slots::new(opts.patch_jobs);
+    let copied_bytes = AtomicU64::new(0);
+    // the error of the file that took the total over max_size. nothing is copied after it
+    let over_max_size: Mutex<Option<Error>> = Mutex::new(None);
+    let copy_time = Mutex::new(Duration::ZERO);
+    let patch_time = Mutex::new(Duration::ZERO);
+    let add_time = |total: &Mutex<Duration>, started: Instant| {
+        *total.lock().unwrap_or_else(|e| e.into_inner()) += started.elapsed();
+    };
+    let patch_file = |file: &PendingCopy| -> Result<PatchOps, Error> {
+        let (output_path, work_path) = (&file.output_path, &file.work_path);
+        // now change the loader to point to the specific one we copied
+        let ops = patch_loader(tools, &interpreter, work_path, &LoaderPatch {
+            replace_needed: file.dep.replace_needed(),
+            lib_rpath: file.lib_rpath.clone(),
+            append_rpath: opts.append_rpath,
+            no_default_lib: file.is_exec && opts.no_default_lib,
+            keep_interpreter: opts.keep_interpreter,
+        })?;
+        if opts.verify_patch {
+            verify_patch_ops(tools, &ops, work_path)?;
+        }
+        if work_path != output_path {
+            std::fs::rename(work_path, output_path)
+                .map_err(|e| Error::Io(format!("Failed to move patched {:?} to {:?}\n{}", work_path, output_path, e)))?;
+        }
+        Ok(ops)
+    };
+    let copy_and_patch = |file: &PendingCopy| -> Result<PatchOps, Error> {
+        let dep_path = &file.dep.path;
+        let (output_path, work_path) = (&file.output_path, &file.work_path);
+        if over_max_size.lock().unwrap_or_else(|e| e.into_inner()).is_some() {
+            return Err(Error::Other(format!("Not copying {}, the bundle is already over the --max-size", file.filename)));
+        }
+        // advisory only, the file is still copied
+        progress.suspend(|| warn_unusual_permissions(dep_path));
+        {
+            let _slot = copy_slots.take();
+            let started = Instant::now();
+            if let Some(parent) = output_path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| Error::Io(format!("Failed to create {:?}\n{}", parent, e)))?;
+            }
+            let copied = std::fs::copy(dep_path, work_path)
+                .map_err(|e| Error::Io(format!("Failed to copy {:?} to {:?}\n{}", dep_path, work_path, e)));
+            add_time(&copy_time, started);
+            copied?;
+            events::emit("copied", Some(&file.filename));
+        }
+
+        let _slot = patch_slots.take();
+        let started = Instant::now();
+        let patched = patch_file(file);
+        add_time(&patch_time, started);
+        if patched.is_ok() {
+            events::emit("patched", Some(&file.filename));
+            if let Err(e) = add_copied_size(&copied_bytes, output_path, opts.max_size) {
+                over_max_size.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert(e);
+            }
+        }
+        patched
+    };
+    let results = run_in_parallel(&pending, opts.copy_jobs.max(opts.patch_jobs), |file| {
+        let result = copy_and_patch(file);
+        progress.inc(1);
+        progress.set_message(file.filename.clone());
+        result
+    });
+    if let Some(e) = over_max_size.into_inner().unwrap_or_else(|e| e.into_inner()) {
+        progress.finish_and_clear();
+        return Err(e);
+    }
+
+    // and finally record how each one went, in order
+    for (file, result) in pending.into_iter().zip(results) {
+        let PendingCopy { dep, filename, is_exec, output_path, work_path, .. } = file;
+        let ops = match result {
+            Ok(ops) => ops,
+            Err(e) if opts.best_effort => {
+                // dont leave a half copied or unpatched file behind, or record it as up to date
+                let _ = std::fs::remove_file(&work_path);
+                let _ = std::fs::remove_file(&output_path);
+                if let Some(state) = &mut state {
+                    state.remove(&filename);
+                }
+                progress.suspend(|| eprintln!("Skipping {} (from {:?}):\n{}", filename, dep.path, e));
+                report.failures.push(filename.clone());
+                if is_exec {
+                    failed_execs.push(filename);
+                }
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if !ops.origin_subdirs.is_empty() {
+            progress.suspend(|| eprintln!(
+                "Warning: {} (from {:?}) had the rpath entries {}, which are not in the archive. its rpath now points at where its needed libs are bundled instead",
+                filename, dep.path, ops.origin_subdirs.join(":")));
+        }
+        for alias in &dep.aliases {
+            let link_path = output_path.with_file_name(alias);
+            if link_path.symlink_metadata().is_ok() {
+                std::fs::remove_file(&link_path)
+                    .map_err(|e| Error::Io(format!("Failed to remove {:?}\n{}", link_path, e)))?;
+            }
+            std::os::unix::fs::symlink(&filename, &link_path)
+                .map_err(|e| Error::Io(format!("Failed to symlink {:?} to {}\n{}", link_path, filename, e)))?;
+        }
+        if ops.count() == 0 {
+            if opts.verbose {
+                progress.suspend(|| eprintln!("{} is already patched, leaving it as is", filename));
+            }
+        } else {
+            report.patched_files += 1;
+            report.patch_operations += ops.count();
+        }
+        if is_exec {
+            report.copied_files.push(opts.wrapped_exec_name(&filename).into());
+        } else if let Ok(relative) = output_path.strip_prefix(archive_path) {
+            report.copied_files.push(relative.to_path_buf());
+        }
+    }
+    report.copy_time = copy_time.into_inner().unwrap_or_else(|e| e.into_inner());
+    report.patch_time = patch_time.into_inner().unwrap_or_else(|e| e.into_inner());
+    progress.finish_and_clear();
+
+    // finally, copy the loader itself
+    let loader_filename = if opts.loader_symlink {
+        loader_real_name(loader)?
+    } else { loader.name.clone() };
+    let mut new_loader_path = archive_path.to_path_buf();
+    new_loader_path.push(&loader_filename);
+    match decide_copy_action(&mut state, &loader_filename, &loader.path, &new_loader_path, opts.merge)? {
+        CopyAction::Copy => {
+            warn_unusual_permissions(&loader.path);
+            let started = Instant::now();
+            std::fs::copy(&loader.path, &new_loader_path)
+                .map_err(|e| Error::Io(format!("Failed to copy loader {:?} to {:?}\n{}", loader.path, new_loader_path, e)))?;
+            report.copy_time += started.elapsed();
+            events::emit("copied", Some(&loader_filename));
+            add_copied_size(&copied_bytes, &new_loader_path, opts.max_size)?;
+        }
+        CopyAction::Unchanged => {}
+        CopyAction::Collision => {
+            report.collisions.push(format!("{} (from {:?})", loader_filename, loader.path));
+        }
+    }
+    report.copied_bytes = copied_bytes.into_inner();
+    if loader_filename != loader.name {
+        let link_path = archive_path.join(&loader.name);
+        if link_path.symlink_metadata().is_ok() {
+            std::fs::remove_file(&link_path)
+                .map_err(|e| Error::Io(format!("Failed to remove {:?}\n{}", link_path, e)))?;
+        }
+        std::os::unix::fs::symlink(&loader_filename, &link_path)
+            .map_err(|e| Error::Io(format!("Failed to symlink {:?} to {}\n{}", link_path, loader_filename, e)))?;
+    }
+
+    // also, if user wants to make a wrapper, we replace the archive_path/execname
+    // with archive_path/.execname-original and make archive_path/execname a shell script
+    // that launches archive_path/.execname-original with the correct LD_LIBRARY_PATH.
+    // with a wrapper_suffix, the executable stays put and the script goes next to it
+    if opts.make_wrapper {
+        for execname in execnames {
+            // dont touch an executable of the same name that was already in the archive
+            if colliding_execs.contains(execname) || failed_execs.contains(execname) {
+                continue;
+            }
+            let old_exec = archive_path.join(execname);
+            let newname = opts.wrapped_exec_name(execname);
+            let new_exec = archive_path.join(&newname);
+            // an unchanged executable was already moved aside by a previous run
+            if newname != *execname && !unchanged_execs.contains(execname) {
+                std::fs::rename(&old_exec, &new_exec)
+                    .map_err(|e| Error::Io(format!("Failed to rename {:?} to {:?}\n{}", old_exec, new_exec, e)))?;
+            }
+            // now make the shell script
+            // the loader file itself, rather than the name the executables reference,
+            // which with loader_symlink is only a symlink to it
+            let wrapper_path = archive_path.join(opts.wrapper_name(execname));
+            let wrapper = make_shell_script_wrapper(&newname, &loader_filename, &opts.wrapper);
+            std::fs::write(&wrapper_path, wrapper)
+                .map_err(|e| Error::Io(format!("Failed to write wrapper {:?}\n{}", wrapper_path, e)))?;
+            // also make it executable:
+            let wrapper_path_str = wrapper_path.to_string_lossy();
+            let exec_args = ["chmod", "+x", &wrapper_path_str];
+            let out = execute_checked(&exec_args)?;
+            if out.status != 0 {
+                return Err(Error::Io(out.stderr));
+            }
+        }
+    }
+
+    // last, since making the wrappers renames the executables and chmods the scripts
+    if let Some(mode) = opts.exec_mode.filter(|_| !opts.libs_only) {
+        for execname in execnames {
+            if colliding_execs.contains(execname) || failed_execs.contains(execname) {
+                continue;
+            }
+            set_mode(&archive_path.join(opts.wrapped_exec_name(execname)), mode)?;
+            if opts.make_wrapper {
+                set_mode(&archive_path.join(opts.wrapper_name(execname)), mode)?;
+            }
+        }
+    }
+
+    if let Some(state) = &state {
+        write_state_file(archive_path, state)?;
+    }
+
+    Ok(report)
+}
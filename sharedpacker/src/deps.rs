@@ -0,0 +1,440 @@
+use std::{path::{Path, PathBuf}, collections::{HashMap, hash_map::Entry}};
+use crate::Error;
+use crate::tools::{ToolConfig, execute_tool};
+use crate::ldd::{SharedLib, get_loader, detect_interpreter};
+use crate::files::{under_sysroot, canonicalize_in_sysroot};
+use crate::color;
+use crate::events;
+use crate::elf::{read_needed, read_soname};
+use crate::elftool::ElfTool;
+
+#[derive(Debug, Clone, Default)]
+pub struct DependencyNode {
+    pub name: String,
+    pub path: PathBuf,
+    pub dependencies: Vec<String>,
+    /// needed entries of this file that are absolute paths, eg: /opt/foo/libbar.so.
+    /// they are listed in dependencies by their basename, which is what
+    /// the copy in the archive gets patched to need instead
+    pub absolute_needed: Vec<String>,
+    /// (old, new) needed entries that the copy in the archive gets patched to need
+    /// by another name, eg: a lib pruned by prune_duplicate_sonames
+    pub renamed_needed: Vec<(String, String)>,
+    /// other names this same file was needed by, eg: libfoo.so when the node is libfoo.so.1
+    /// and both resolve to one file. the archive has a symlink to the copy for each of them
+    pub aliases: Vec<String>,
+}
+
+impl DependencyNode {
+    /// the (old, new) needed entries to rename in the copy of this file in the archive.
+    /// an absolute_needed entry becomes its basename, unless that is renamed too
+    pub fn replace_needed(&self) -> Vec<(String, String)> {
+        let basename = |needed: &str| needed.rsplit('/').next().unwrap_or(needed).to_string();
+        let renamed = |name: String| self.renamed_needed.iter()
+            .find(|(old, _)| *old == name).map(|(_, new)| new.clone()).unwrap_or(name);
+        let mut replace: Vec<(String, String)> = self.absolute_needed.iter()
+            .map(|needed| (needed.clone(), renamed(basename(needed))))
+            .collect();
+        for (old, new) in &self.renamed_needed {
+            if !self.absolute_needed.iter().any(|needed| basename(needed) == *old) {
+                replace.push((old.clone(), new.clone()));
+            }
+        }
+        replace
+    }
+}
+
+/// use patchelf, or with native_elf goblin, to find a list of needed libs from an executable
+pub fn print_needed(
+    tools: &ToolConfig,
+    path: &Path,
+) -> Result<Vec<String>, Error> {
+    if tools.native_elf {
+        read_needed(path)
+    } else {
+        let strthing: &str = path.to_str().map_or_else(|| Err("Failed to get path as string"), Ok)?;
+        let exec_args = [
+            &tools.patchelf, "--print-needed", strthing,
+        ];
+        let output = execute_tool(tools, &exec_args)?;
+        if output.status != 0 {
+            return Err(Error::Other(output.stderr));
+        }
+        Ok(output.stdout.lines().map(|l| l.to_string()).collect())
+    }
+}
+
+/// the needed libs of a file, see ElfTool::needed. a needed entry for any of the loader_names
+/// is left out, eg: glibc's libc.so.6 needs ld-linux-x86-64.so.2, which is bundled as the
+/// loader rather than as a lib
+pub fn get_needed_libs(
+    tools: &dyn ElfTool,
+    path: &Path,
+    loader_names: &[String],
+) -> Result<Vec<String>, Error> {
+    let needed = tools.needed(path)?;
+
+    let mut outvec = vec![];
+    for line in needed {
+        let trimmed: String = line.trim_start().trim_end().into();
+        if loader_names.iter().any(|loader| trimmed.rsplit('/').next() == Some(loader.as_str())) {
+            continue;
+        }
+        outvec.push(trimmed);
+    }
+
+    Ok(outvec)
+}
+
+/// the real path of a lib, with symlinks and .. segments resolved, so that
+/// one file is always known by the same path however it was reached.
+/// the symlinks of a lib under the sysroot are followed within it, see canonicalize_in_sysroot
+fn canonical_lib_path(lib_name: &str, sysroot: Option<&Path>, path: &Path) -> Result<PathBuf, Error> {
+    canonicalize_in_sysroot(sysroot, path).map_err(|e| Error::MissingDependency(format!(
+        "Failed to resolve the path {:?} of {}, it might be a broken symlink\n{}", path, lib_name, e)))
+}
+
+/// where the loader would have looked for the libs of needed_path, and what to do
+/// about one that it could not find, to go after a not found error
+fn not_found_hint(
+    tools: &dyn ElfTool,
+    needed_name: &str,
+    needed_path: &Path,
+    sysroot: Option<&Path>,
+) -> String {
+    let rpath = tools.rpath(needed_path).ok().flatten().unwrap_or_default();
+    let ld_library_path = std::env::var("LD_LIBRARY_PATH").unwrap_or_default();
+    let sysroot_hint = match sysroot {
+        Some(root) => format!("check that it is under the --sysroot {:?}", root),
+        None => "pass --sysroot if it is under another root".to_string(),
+    };
+    format!("  while resolving the libs of: {} ({:?})\n  \
+        searched: the rpath of {} ({:?}), LD_LIBRARY_PATH ({:?}), /etc/ld.so.cache, and the default lib dirs\n  \
+        hint: set LD_LIBRARY_PATH to the directory that has it, or {}",
+        needed_name, needed_path, needed_name, rpath, ld_library_path, sysroot_hint)
+}
+
+/// makes the lib of this name resolve to path instead of to what ldd reports, by putting
+/// it in the location map before any traversal. warns if the file's soname is not name
+pub fn map_lib(
+    known_lib_location_map: &mut HashMap<String, PathBuf>,
+    name: &str,
+    path: &Path,
+) -> Result<(), Error> {
+    if !path.is_file() {
+        return Err(Error::MissingDependency(format!("The file {:?} that {} is mapped to does not exist", path, name)));
+    }
+    match read_soname(path)? {
+        Some(soname) if soname == name => {}
+        Some(soname) => eprintln!("Warning: {} is mapped to {:?}, whose SONAME is {}", name, path, soname),
+        None => eprintln!("Warning: {} is mapped to {:?}, which has no SONAME", name, path),
+    }
+    known_lib_location_map.insert(name.into(), canonical_lib_path(name, None, path)?);
+    Ok(())
+}
+
+/// settings for traverse_dependencies
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraverseOptions<'a> {
+    /// a needed entry for one of these is not traversed, since the loader is bundled separately
+    pub loader_names: &'a [String],
+    /// if given, the lib paths that ldd reports are read from under it
+    pub sysroot: Option<&'a Path>,
+    /// only this many levels of libs below needed_path are traversed, and the libs at the
+    /// last level get nodes without dependencies, eg: Some(1) for only the direct libs
+    pub max_depth: Option<usize>,
+    /// print each lib as it is found to stderr
+    pub verbose: bool,
+    /// put in front of each lib that verbose prints, one more indent for each level
+    pub log_prefix: &'a str,
+}
+
+/// the paths of the libs are canonicalized, but each lib keeps the name it
+/// is needed by, which is the name it gets in the archive
+pub fn traverse_dependencies(
+    tools: &dyn ElfTool,
+    known_lib_location_map: &mut HashMap<String, PathBuf>,
+    use_libs: &mut Vec<String>,
+    dependency_nodes: &mut Vec<DependencyNode>,
+    needed_path: &Path,
+    needed_name: &str,
+    opts: &TraverseOptions,
+) -> Result<(), Error> {
+    let TraverseOptions { loader_names, sysroot, max_depth, verbose, log_prefix } = *opts;
+    if max_depth == Some(0) {
+        dependency_nodes.push(DependencyNode {
+            name: needed_name.into(),
+            path: needed_path.into(),
+            ..Default::default()
+        });
+        return Ok(());
+    }
+    // eprintln!("Looking for needed: {:?}", needed_path);
+    // first we iterate over its dependencies, and add the known paths
+    // to our map:
+    let shared_libs = tools.list_libs(needed_path).map_err(|e| match e {
+        Error::MissingDependency(s) => Error::MissingDependency(
+            format!("{}\n{}", s, not_found_hint(tools, needed_name, needed_path, sysroot))),
+        e => e,
+    })?;
+    for lib in shared_libs {
+        // eprintln!("PATH: {:?}", lib);
+        if let Entry::Vacant(entry) = known_lib_location_map.entry(lib.name) {
+            let lib_path = canonical_lib_path(entry.key(), sysroot, &under_sysroot(sysroot, &lib.path))?;
+            entry.insert(lib_path);
+        }
+    }
+
+    let mut dependency_node = DependencyNode {
+        name: needed_name.into(),
+        path: needed_path.into(),
+        ..Default::default()
+    };
+    // next we get all of the actually needed dependencies of this file
+    // and for each dependency, we recurse and do this process again, each
+    // time appending the use_libs list of libs that we will ultimately use
+    let needed_shared_libs = get_needed_libs(tools, needed_path, loader_names)?;
+    for needed in needed_shared_libs {
+        // an absolute path needed lib gets bundled next to everything else
+        // under its basename, so that is the name we track it by
+        let lib = if needed.starts_with('/') {
+            dependency_node.absolute_needed.push(needed.clone());
+            needed.rsplit('/').next().unwrap_or(&needed).to_string()
+        } else { needed.clone() };
+        dependency_node.dependencies.push(lib.clone());
+
+        // find this libs path from our map. an absolute path that ldd didnt
+        // report can still be found where it says it is
+        let lib_path = match known_lib_location_map.get(&lib) {
+            Some(p) => p.clone(),
+            None if needed.starts_with('/') && under_sysroot(sysroot, Path::new(&needed)).is_file() => {
+                canonical_lib_path(&lib, sysroot, &under_sysroot(sysroot, Path::new(&needed)))?
+            }
+            None => {
+                return Err(Error::MissingDependency(format!(
+                    "Found needed library that we don't know a location of: {}\n{}",
+                    lib, not_found_hint(tools, needed_name, needed_path, sysroot))));
+            }
+        };
+
+        // dont recurse for a lib name that weve already found. its libs were
+        // already resolved the first time, which the verbose output marks
+        let next_log_prefix = format!("{}  ", log_prefix);
+        if use_libs.contains(&lib) {
+            if verbose {
+                eprintln!("{}{} {} {} {}", next_log_prefix, color::name(&lib), color::dim("=>"),
+                    color::path(&format!("{:?}", lib_path)), color::dim("[cached]"));
+            }
+        } else {
+            if verbose {
+                eprintln!("{}{} {} {}", next_log_prefix, color::name(&lib), color::dim("=>"), color::path(&format!("{:?}", lib_path)));
+            }
+
+            // prevent duplicates (yes its inefficient, but
+            use_libs.push(lib.clone());
+            events::emit("resolved", Some(&lib));
+
+            let next_opts = TraverseOptions { max_depth: max_depth.map(|d| d - 1), log_prefix: &next_log_prefix, ..*opts };
+            traverse_dependencies(
+                tools, known_lib_location_map, use_libs, dependency_nodes, &lib_path, &lib, &next_opts)?;
+        }
+    }
+
+    dependency_nodes.push(dependency_node);
+    Ok(())
+}
+
+/// follows the dependency edges from the root names and returns the names
+/// of the nodes that none of the roots reach, in the order of the nodes
+pub fn find_unreachable(
+    dependency_nodes: &[DependencyNode],
+    roots: &[String],
+) -> Vec<String> {
+    let nodes_by_name: HashMap<&str, &DependencyNode> = dependency_nodes.iter()
+        .flat_map(|n| std::iter::once(&n.name).chain(&n.aliases).map(move |name| (name.as_str(), n)))
+        .collect();
+    let mut reached: Vec<&str> = vec![];
+    let mut to_visit: Vec<&str> = roots.iter().map(|r| r.as_str()).collect();
+    while let Some(name) = to_visit.pop() {
+        if reached.contains(&name) {
+            continue;
+        }
+        reached.push(name);
+        if let Some(node) = nodes_by_name.get(name) {
+            to_visit.extend(node.dependencies.iter().map(|d| d.as_str()));
+        }
+    }
+
+    dependency_nodes.iter()
+        .filter(|n| !reached.contains(&n.name.as_str()))
+        .map(|n| n.name.clone())
+        .collect()
+}
+
+/// keeps one node per file, comparing paths after following symlinks, so that
+/// the same file reached twice is only copied and patched once. the first node is
+/// kept, and takes on the dependencies of the dropped ones. a dropped node that reached
+/// the file under another name becomes an alias of the kept one, so that name is still in the archive
+pub fn dedupe_by_path(dependency_nodes: Vec<DependencyNode>) -> Vec<DependencyNode> {
+    let mut deduped: Vec<DependencyNode> = vec![];
+    let mut seen: Vec<PathBuf> = vec![];
+    for node in dependency_nodes {
+        let key = std::fs::canonicalize(&node.path).unwrap_or_else(|_| node.path.clone());
+        match seen.iter().position(|k| *k == key) {
+            Some(index) => {
+                let kept = &mut deduped[index];
+                for name in std::iter::once(node.name).chain(node.aliases) {
+                    if name != kept.name && !kept.aliases.contains(&name) {
+                        kept.aliases.push(name);
+                    }
+                }
+                for dep in node.dependencies {
+                    if !kept.dependencies.contains(&dep) {
+                        kept.dependencies.push(dep);
+                    }
+                }
+                for needed in node.absolute_needed {
+                    if !kept.absolute_needed.contains(&needed) {
+                        kept.absolute_needed.push(needed);
+                    }
+                }
+            }
+            None => {
+                seen.push(key);
+                deduped.push(node);
+            }
+        }
+    }
+    deduped
+}
+
+/// finds libs that have the same SONAME as a lib earlier in the list. the loader only
+/// needs one of them at runtime, so each such duplicate is left out, and the files that
+/// needed it are renamed to need the lib that is kept instead. the executables are never
+/// pruned. returns the (pruned, kept) names
+pub fn prune_duplicate_sonames(
+    tools: &dyn ElfTool,
+    dependency_nodes: &mut Vec<DependencyNode>,
+    execnames: &[String],
+) -> Result<Vec<(String, String)>, Error> {
+    let mut sonames: HashMap<String, String> = HashMap::new();
+    let mut pruned: Vec<(String, String)> = vec![];
+    let mut kept_nodes = vec![];
+    for node in std::mem::take(dependency_nodes) {
+        let soname = if execnames.contains(&node.name) {
+            None
+        } else {
+            tools.soname(&node.path)?
+        };
+        match soname.filter(|s| !s.is_empty()) {
+            Some(soname) => match sonames.entry(soname) {
+                Entry::Occupied(kept) => pruned.push((node.name, kept.get().clone())),
+                Entry::Vacant(entry) => {
+                    entry.insert(node.name.clone());
+                    kept_nodes.push(node);
+                }
+            },
+            None => kept_nodes.push(node),
+        }
+    }
+    for node in kept_nodes.iter_mut() {
+        for (old, new) in &pruned {
+            if let Some(index) = node.dependencies.iter().position(|d| d == old) {
+                node.dependencies.remove(index);
+                if !node.dependencies.contains(new) {
+                    node.dependencies.insert(index, new.clone());
+                }
+                node.renamed_needed.push((old.clone(), new.clone()));
+            }
+        }
+    }
+    *dependency_nodes = kept_nodes;
+    Ok(pruned)
+}
+
+/// get_loader for the executable, with its path rooted at the sysroot if there is one, and
+/// canonicalized. the loader keeps the name the executable references it by, which is
+/// the name that it is copied under and that patch_loader points the interpreter at
+pub fn resolve_loader(
+    tools: &dyn ElfTool,
+    exe: &Path,
+    sysroot: Option<&Path>,
+) -> Result<SharedLib, Error> {
+    let mut loader = get_loader(tools, exe)?;
+    loader.path = canonical_lib_path(&loader.name, sysroot, &under_sysroot(sysroot, &loader.path))?;
+    Ok(loader)
+}
+
+/// fails if any of the executables has a loader other than the given one, since every
+/// executable in an archive is patched to use the same loader. the loaders are compared
+/// after following symlinks. executables without an interpreter are left out
+pub fn check_single_loader(
+    tools: &dyn ElfTool,
+    exepaths: &[PathBuf],
+    loader: &SharedLib,
+    sysroot: Option<&Path>,
+) -> Result<(), Error> {
+    let mut others = vec![];
+    for exe in exepaths {
+        if let Some(other) = detect_interpreter(tools, exe)? {
+            let other_path = under_sysroot(sysroot, &other.path);
+            if canonicalize_in_sysroot(sysroot, &other_path).unwrap_or_else(|_| other_path.clone()) != loader.path {
+                others.push(format!("{:?} needs {:?}", exe, other.path));
+            }
+        }
+    }
+    if others.is_empty() {
+        return Ok(());
+    }
+    Err(Error::Other(format!(
+        "The executables need different loaders, but are all bundled with {:?}, the loader of the first one:\n  {}\n\
+        pack them into separate outputs instead, eg: with --jobs-file",
+        loader.path, others.join("\n  "))))
+}
+
+/// the names a lib can need the loader by: the name the executable references it by,
+/// and its soname, which differ when the executable's interpreter is a symlink
+pub fn loader_names(loader: &SharedLib) -> Vec<String> {
+    let mut names = vec![loader.name.clone()];
+    if let Ok(Some(soname)) = read_soname(&loader.path) {
+        if soname != loader.name {
+            names.push(soname);
+        }
+    }
+    names
+}
+
+/// settings for resolve_dependencies
+#[derive(Debug, Clone, Default)]
+pub struct ResolveOptions {
+    /// see traverse_dependencies
+    pub sysroot: Option<PathBuf>,
+    /// print each lib as it is found to stderr
+    pub verbose: bool,
+}
+
+/// finds every lib that an executable needs, directly or through other libs, and
+/// its loader. nothing is copied or patched. this is what the cli does before packing
+pub fn resolve_dependencies(
+    tools: &dyn ElfTool,
+    exe: &Path,
+    opts: &ResolveOptions,
+) -> Result<(Vec<DependencyNode>, SharedLib), Error> {
+    let execname = exe.file_name()
+        .ok_or_else(|| Error::Other(format!("Failed to get exec path file name from {:?}", exe)))?
+        .to_string_lossy().to_string();
+    let loader = resolve_loader(tools, exe, opts.sysroot.as_deref())?;
+    let mut lib_location_map = HashMap::new();
+    let mut used_libs = vec![];
+    let mut dependencies = vec![];
+    let loader_names = loader_names(&loader);
+    let traverse_opts = TraverseOptions {
+        loader_names: &loader_names,
+        sysroot: opts.sysroot.as_deref(),
+        verbose: opts.verbose,
+        ..Default::default()
+    };
+    traverse_dependencies(
+        tools, &mut lib_location_map, &mut used_libs, &mut dependencies, exe, &execname, &traverse_opts)?;
+    Ok((dedupe_by_path(dependencies), loader))
+}
@@ -0,0 +1,32 @@
+use crate::wrapper::shell_escape_double_quoted;
+
+/// escapes a string value of a desktop entry key
+fn escape_value(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\n', "\\n").replace('\t', "\\t").replace('\r', "\\r")
+}
+
+/// a freedesktop .desktop file that launches the wrapper, which lives next to it in the
+/// output. Exec is the bare wrapper name, as an AppDir expects it to be
+pub fn make_desktop_entry(
+    name: &str,
+    wrapper_name: &str,
+    icon: Option<&str>,
+) -> String {
+    // the Exec key has the same quoting rules as a double quoted shell string,
+    // and then its value is escaped like any other string
+    let needs_quotes = wrapper_name.chars().any(|c| " \t\n\"'\\><~|&;$*?#()`".contains(c));
+    let exec = if needs_quotes {
+        format!("\"{}\"", shell_escape_double_quoted(wrapper_name))
+    } else { wrapper_name.to_string() };
+
+    let mut out = String::new();
+    out.push_str("[Desktop Entry]\n");
+    out.push_str("Type=Application\n");
+    out.push_str(&format!("Name={}\n", escape_value(name)));
+    out.push_str(&format!("Exec={}\n", escape_value(&exec)));
+    if let Some(icon) = icon {
+        out.push_str(&format!("Icon={}\n", escape_value(icon)));
+    }
+    out.push_str("Terminal=false\n");
+    out
+}
@@ -0,0 +1,89 @@
+use std::path::Path;
+use crate::Error;
+use crate::tools::{ToolConfig, execute_checked, execute_tool};
+
+/// the tools that are only needed for some things, besides ldd and patchelf, and what for
+pub const OPTIONAL_TOOLS: [(&str, &str); 2] = [
+    ("upx", "needed for --upx"),
+    ("cc", "needed to run the tests"),
+];
+
+/// what doctor found out about one tool
+#[derive(Debug)]
+pub struct ToolStatus {
+    pub name: String,
+    /// the first line that the tool's --version printed. None if it could not be run
+    pub version: Option<String>,
+    /// why the tool cannot be used, eg: it is missing or its patchelf is too old
+    pub problem: Option<String>,
+    /// whether a problem with this tool fails the doctor run
+    pub required: bool,
+}
+
+/// runs tool --version and returns the first line it printed, on stdout or stderr.
+/// an exit code is not checked, since eg: musl's ldd exits 1 after printing its version
+pub fn tool_version(tool: &str) -> Result<String, Error> {
+    let output = execute_checked(&[tool, "--version"])?;
+    let line = output.stdout.lines().chain(output.stderr.lines())
+        .map(|l| l.trim()).find(|l| !l.is_empty())
+        .unwrap_or("unknown version");
+    Ok(line.to_string())
+}
+
+/// makes sure patchelf can do what packing asks of it, by running it on a copy of
+/// probe: reading its needed libs and interpreter, then setting the interpreter
+/// to what it already was. an old patchelf fails on the flags it doesnt know
+pub fn check_patchelf_features(tools: &ToolConfig, probe: &Path) -> Result<(), Error> {
+    let dir = std::env::temp_dir().join(format!("sharedpacker-doctor-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| Error::Io(format!("Failed to create {:?}\n{}", dir, e)))?;
+    let _remove = crate::files::RemoveOnDrop::new(dir.clone());
+    let copy = dir.join("probe");
+    std::fs::copy(probe, &copy)
+        .map_err(|e| Error::Io(format!("Failed to copy {:?} to {:?}\n{}", probe, copy, e)))?;
+    let copy_str = copy.to_string_lossy().to_string();
+
+    let run = |args: &[&str]| -> Result<String, Error> {
+        let mut exec_args = vec![tools.patchelf.as_str()];
+        exec_args.extend(args);
+        exec_args.push(&copy_str);
+        let output = execute_tool(tools, &exec_args)?;
+        if output.status != 0 {
+            return Err(Error::Patch(format!("patchelf {} failed, it may be too old\n{}", args[0], output.stderr)));
+        }
+        Ok(output.stdout.trim().trim_end_matches('\0').to_string())
+    };
+    run(&["--print-needed"])?;
+    let interpreter = run(&["--print-interpreter"])?;
+    run(&["--set-interpreter", &interpreter])?;
+    Ok(())
+}
+
+/// checks ldd and patchelf, which are always required, and the OPTIONAL_TOOLS,
+/// which are only required when they are in require. any other name in
+/// require is checked as a required tool too. probe is a dynamically linked
+/// executable to try patchelf on
+pub fn check_tools(tools: &ToolConfig, probe: &Path, require: &[String]) -> Vec<ToolStatus> {
+    let mut statuses = vec![];
+    let mut check = |name: &str, required: bool, extra: Option<&dyn Fn() -> Result<(), Error>>| {
+        let (version, problem) = match tool_version(name) {
+            Ok(version) => {
+                let problem = extra.and_then(|extra| extra().err()).map(|e| e.to_string());
+                (Some(version), problem)
+            }
+            Err(e) => (None, Some(e.to_string())),
+        };
+        statuses.push(ToolStatus { name: name.to_string(), version, problem, required });
+    };
+    check(&tools.ldd, true, None);
+    check(&tools.patchelf, true, Some(&|| check_patchelf_features(tools, probe)));
+    for (name, _) in OPTIONAL_TOOLS {
+        check(name, require.iter().any(|r| r == name), None);
+    }
+    for name in require {
+        if !OPTIONAL_TOOLS.iter().any(|(optional, _)| optional == name) {
+            check(name, true, None);
+        }
+    }
+    statuses
+}
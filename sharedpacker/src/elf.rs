@@ -0,0 +1,127 @@
+use std::{io::Read, path::Path};
+use crate::Error;
+use crate::ldd::SharedLib;
+use crate::deps::DependencyNode;
+
+/// the parts of an ELF header that decide whether two objects can be loaded together
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ElfArch {
+    /// 1 for 32 bit, 2 for 64 bit
+    pub class: u8,
+    /// the e_machine field, eg: 62 for x86-64
+    pub machine: u16,
+}
+
+impl std::fmt::Display for ElfArch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bits = match self.class {
+            1 => "32-bit",
+            2 => "64-bit",
+            _ => "unknown-class",
+        };
+        let machine = match self.machine {
+            2 => "SPARC".to_string(),
+            3 => "x86".to_string(),
+            8 => "MIPS".to_string(),
+            20 => "PowerPC".to_string(),
+            21 => "PowerPC64".to_string(),
+            22 => "S390".to_string(),
+            40 => "ARM".to_string(),
+            62 => "x86-64".to_string(),
+            183 => "AArch64".to_string(),
+            243 => "RISC-V".to_string(),
+            other => format!("machine {}", other),
+        };
+        write!(f, "{} {}", bits, machine)
+    }
+}
+
+/// reads the class and machine out of an ELF file's header
+pub fn read_elf_arch(path: &Path) -> Result<ElfArch, Error> {
+    // e_ident is 16 bytes, then e_type (2 bytes) then e_machine (2 bytes)
+    let mut header = [0u8; 20];
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| Error::Io(format!("Failed to open {:?}\n{}", path, e)))?;
+    file.read_exact(&mut header)
+        .map_err(|e| Error::Io(format!("Failed to read ELF header of {:?}\n{}", path, e)))?;
+    if header[0..4] != *b"\x7fELF" {
+        return Err(Error::Other(format!("{:?} is not an ELF file", path)));
+    }
+    let machine_bytes = [header[18], header[19]];
+    let machine = match header[5] {
+        2 => u16::from_be_bytes(machine_bytes),
+        _ => u16::from_le_bytes(machine_bytes),
+    };
+    Ok(ElfArch { class: header[4], machine })
+}
+
+/// parses an ELF file with goblin. reads the whole file, but runs nothing
+fn parse_elf<T>(path: &Path, read: impl FnOnce(&goblin::elf::Elf, &[u8]) -> T) -> Result<T, Error> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| Error::Io(format!("Failed to read {:?}\n{}", path, e)))?;
+    let elf = goblin::elf::Elf::parse(&bytes)
+        .map_err(|e| Error::Other(format!("Failed to parse ELF file {:?}\n{}", path, e)))?;
+    Ok(read(&elf, &bytes))
+}
+
+/// the DT_NEEDED entries of an ELF file, like patchelf --print-needed
+pub fn read_needed(path: &Path) -> Result<Vec<String>, Error> {
+    parse_elf(path, |elf, _| elf.libraries.iter().map(|l| l.to_string()).collect())
+}
+
+/// the PT_INTERP path of an ELF file, like patchelf --print-interpreter. None if it has none
+pub fn read_interpreter(path: &Path) -> Result<Option<String>, Error> {
+    // a PT_INTERP that was rewritten in place can keep the padding of a longer one
+    parse_elf(path, |elf, _| elf.interpreter.map(|i| i.trim_end_matches('\0').to_string()))
+}
+
+/// whether an ELF file has a PT_INTERP program header, ie: whether it names a loader.
+/// shared libs and statically linked executables dont, and so have no interpreter to patch
+pub fn has_interpreter(path: &Path) -> Result<bool, Error> {
+    Ok(read_interpreter(path)?.is_some())
+}
+
+/// the DT_RUNPATH of an ELF file, or its DT_RPATH if it has no runpath, like patchelf
+/// --print-rpath. empty if it has neither. None if it is statically linked
+pub fn read_rpath(path: &Path) -> Result<Option<String>, Error> {
+    parse_elf(path, |elf, _| {
+        elf.dynamic.as_ref()?;
+        let entries = if elf.runpaths.is_empty() { &elf.rpaths } else { &elf.runpaths };
+        Some(entries.join(":"))
+    })
+}
+
+/// the DT_SONAME of an ELF file. None if it has none
+pub fn read_soname(path: &Path) -> Result<Option<String>, Error> {
+    parse_elf(path, |elf, _| elf.soname.map(|s| s.to_string()))
+}
+
+/// the GNU build-id note of an ELF file in hex, as readelf --notes prints it. None if it has none
+pub fn read_build_id(path: &Path) -> Result<Option<String>, Error> {
+    const NT_GNU_BUILD_ID: u32 = 3;
+    parse_elf(path, |elf, bytes| {
+        elf.iter_note_headers(bytes).into_iter().flatten()
+            .filter_map(|note| note.ok())
+            .find(|note| note.n_type == NT_GNU_BUILD_ID && note.name.trim_end_matches('\0') == "GNU")
+            .map(|note| note.desc.iter().map(|b| format!("{:02x}", b)).collect())
+    })
+}
+
+/// makes sure every dependency (and the loader) has the same architecture as the
+/// main executable, so a stray 32-bit lib doesnt end up in a 64-bit bundle
+pub fn check_architectures(
+    main_exe: &Path,
+    dependencies: &[DependencyNode],
+    loader: &SharedLib,
+) -> Result<(), Error> {
+    let expected = read_elf_arch(main_exe)?;
+    let paths = dependencies.iter().map(|d| &d.path).chain(std::iter::once(&loader.path));
+    for path in paths {
+        let arch = read_elf_arch(path)?;
+        if arch != expected {
+            return Err(Error::Other(format!(
+                "{:?} is {}, but {:?} is {}", path, arch, main_exe, expected)));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::path::Path;
+use crate::Error;
+use crate::tools::{ToolConfig, execute_tool};
+use crate::ldd::{SharedLib, parse_ldd_output, ldd_loader};
+use crate::patch::{PatchOps, get_interpreter, print_field};
+use crate::deps::print_needed;
+use crate::elf::{read_interpreter, read_rpath, read_soname};
+
+/// how traversing and patching read and change ELF files, so that something other than
+/// ldd and patchelf can do it, eg: a fake in a test. ToolConfig is the implementation that
+/// runs ldd and patchelf, and with native_elf reads the needed libs, interpreter, rpath and soname with goblin
+pub trait ElfTool: Sync {
+    /// the libs that path resolves to, like ldd reports them, without the loader
+    fn list_libs(&self, path: &Path) -> Result<Vec<SharedLib>, Error>;
+
+    /// the DT_NEEDED entries of path, in order
+    fn needed(&self, path: &Path) -> Result<Vec<String>, Error>;
+
+    /// the PT_INTERP of path. None if it has none
+    fn interpreter(&self, path: &Path) -> Result<Option<String>, Error>;
+
+    /// the rpath (or runpath) of path, empty if it has none. None if it is statically linked
+    fn rpath(&self, path: &Path) -> Result<Option<String>, Error>;
+
+    /// the DT_SONAME of path. None (or empty) if it has none
+    fn soname(&self, path: &Path) -> Result<Option<String>, Error>;
+
+    /// runs a program and returns its output, eg: the bundled loader with --verify
+    fn run(&self, exe_and_args: &[&str]) -> Result<exechelper::CommandOutput, Error>;
+
+    fn set_interpreter(&self, path: &Path, interpreter: &str) -> Result<(), Error>;
+
+    fn set_rpath(&self, path: &Path, rpath: &str) -> Result<(), Error>;
+
+    /// applies every one of ops to path. by default with set_interpreter and set_rpath, so this
+    /// fails for ops that rename needed entries or set no_default_lib unless it is overridden
+    fn apply_patch_ops(&self, path: &Path, ops: &PatchOps) -> Result<(), Error> {
+        if !ops.replace_needed.is_empty() || ops.no_default_lib {
+            return Err(Error::Patch(format!(
+                "Cannot rename the needed libs of {:?} or set no-default-lib on it, this ElfTool only sets the interpreter and rpath", path)));
+        }
+        if let Some(interpreter) = &ops.set_interpreter {
+            self.set_interpreter(path, interpreter)?;
+        }
+        if let Some(rpath) = &ops.set_rpath {
+            self.set_rpath(path, rpath)?;
+        }
+        Ok(())
+    }
+}
+
+impl ElfTool for ToolConfig {
+    fn list_libs(&self, path: &Path) -> Result<Vec<SharedLib>, Error> {
+        parse_ldd_output(self, path)
+    }
+
+    fn needed(&self, path: &Path) -> Result<Vec<String>, Error> {
+        print_needed(self, path)
+    }
+
+    /// natively with native_elf, otherwise with patchelf --print-interpreter, so that an
+    /// interpreter with any name or path is found. only if patchelf fails is it guessed from
+    /// the loader that ldd reports. a file without a PT_INTERP is never given one this way,
+    /// even though ldd reports a loader for a shared lib too
+    fn interpreter(&self, path: &Path) -> Result<Option<String>, Error> {
+        let interpreter = read_interpreter(path)?;
+        if interpreter.is_none() || self.native_elf {
+            return Ok(interpreter);
+        }
+        match get_interpreter(self, path) {
+            Ok(interpreter) => Ok(interpreter),
+            Err(_) => Ok(ldd_loader(self, path)?.map(|lib| lib.path.to_string_lossy().to_string())),
+        }
+    }
+
+    fn rpath(&self, path: &Path) -> Result<Option<String>, Error> {
+        if self.native_elf {
+            read_rpath(path)
+        } else {
+            print_field(self, "--print-rpath", path)
+        }
+    }
+
+    fn soname(&self, path: &Path) -> Result<Option<String>, Error> {
+        if self.native_elf {
+            read_soname(path)
+        } else {
+            print_field(self, "--print-soname", path)
+        }
+    }
+
+    /// with the C locale and the retries of execute_tool
+    fn run(&self, exe_and_args: &[&str]) -> Result<exechelper::CommandOutput, Error> {
+        execute_tool(self, exe_and_args)
+    }
+
+    fn set_interpreter(&self, path: &Path, interpreter: &str) -> Result<(), Error> {
+        let ops = PatchOps { set_interpreter: Some(interpreter.into()), ..Default::default() };
+        crate::patch::apply_patch_ops(self, &ops, path)
+    }
+
+    fn set_rpath(&self, path: &Path, rpath: &str) -> Result<(), Error> {
+        let ops = PatchOps { set_rpath: Some(rpath.into()), ..Default::default() };
+        crate::patch::apply_patch_ops(self, &ops, path)
+    }
+
+    /// all of them with a single patchelf run
+    fn apply_patch_ops(&self, path: &Path, ops: &PatchOps) -> Result<(), Error> {
+        crate::patch::apply_patch_ops(self, ops, path)
+    }
+}
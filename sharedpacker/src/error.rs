@@ -0,0 +1,58 @@
+/// the category of a failure, which decides the exit code
+#[derive(Debug)]
+pub enum Error {
+    /// ldd, patchelf, or another external tool could not be run
+    MissingTool(String),
+    /// a needed library could not be found
+    MissingDependency(String),
+    /// reading, copying, or writing a file failed
+    Io(String),
+    /// patchelf ran but failed to modify a file
+    Patch(String),
+    Other(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Other(_) => 1,
+            Error::MissingTool(_) => 2,
+            Error::MissingDependency(_) => 3,
+            Error::Io(_) => 4,
+            Error::Patch(_) => 5,
+        }
+    }
+
+    /// puts "prefix: " in front of the message, keeping the category
+    pub fn context(self, prefix: &str) -> Error {
+        let add = |s: String| format!("{}: {}", prefix, s);
+        match self {
+            Error::MissingTool(s) => Error::MissingTool(add(s)),
+            Error::MissingDependency(s) => Error::MissingDependency(add(s)),
+            Error::Io(s) => Error::Io(add(s)),
+            Error::Patch(s) => Error::Patch(add(s)),
+            Error::Other(s) => Error::Other(add(s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::MissingTool(s) | Error::MissingDependency(s) | Error::Io(s)
+                | Error::Patch(s) | Error::Other(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(s: String) -> Self {
+        Error::Other(s)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(s: &str) -> Self {
+        Error::Other(s.into())
+    }
+}
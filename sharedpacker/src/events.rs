@@ -0,0 +1,39 @@
+use std::{sync::atomic::{AtomicBool, Ordering}, time::{SystemTime, UNIX_EPOCH}};
+use serde::Serialize;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// turns on --progress-json, after which every emit writes a line to stderr
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// one line of --progress-json
+#[derive(Debug, Serialize)]
+pub struct Event<'a> {
+    pub event: &'a str,
+    /// the lib or executable the event is about
+    pub name: Option<&'a str>,
+    /// milliseconds since the unix epoch
+    pub timestamp_ms: u128,
+}
+
+/// a single line json object for one event
+pub fn make_event_json(event: &str, name: Option<&str>, timestamp_ms: u128) -> String {
+    serde_json::to_string(&Event { event, name, timestamp_ms }).expect("an event is only strings and a number")
+}
+
+/// writes an event to stderr as one json line if --progress-json is on. the events are
+/// resolve_started, resolved for every lib and the loader, copied and patched for every
+/// file, and done once the output is complete
+pub fn emit(event: &str, name: Option<&str>) {
+    if !enabled() {
+        return;
+    }
+    let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default();
+    eprintln!("{}", make_event_json(event, name, timestamp_ms));
+}
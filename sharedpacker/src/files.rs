@@ -0,0 +1,429 @@
+use std::{ffi::OsString, path::{Path, PathBuf}, io::BufRead, sync::Mutex};
+use crate::Error;
+use crate::tools::execute_checked;
+
+/// uses sha256sum to get the hex digest of a file's contents
+pub fn sha256_file(path: &Path) -> Result<String, Error> {
+    let path_str = path.to_string_lossy();
+    let exec_args = ["sha256sum", &path_str];
+    let output = execute_checked(&exec_args)?;
+    if output.status != 0 {
+        return Err(Error::Io(output.stderr));
+    }
+    match output.stdout.split_whitespace().next() {
+        Some(hash) => Ok(hash.into()),
+        None => Err(Error::Other(format!("Failed to parse sha256sum output for {:?}", path))),
+    }
+}
+
+/// roots an absolute path that ldd reported at the sysroot, if there is one.
+/// eg: /lib/libfoo.so.6 with a sysroot of /build/root is /build/root/lib/libfoo.so.6
+pub fn under_sysroot(sysroot: Option<&Path>, path: &Path) -> PathBuf {
+    match sysroot {
+        Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+        None => path.into(),
+    }
+}
+
+/// like fs::canonicalize, but for a path under the sysroot, whose symlinks are followed
+/// as they would be on the target: an absolute target is rooted at the sysroot rather
+/// than at the host's /, and .. never leaves the sysroot. eg: in an extracted rootfs,
+/// lib/libc.so.6 -> /lib/x86_64-linux-gnu/libc-2.31.so is the libc under the sysroot.
+/// without a sysroot, or for a path outside of it, this is fs::canonicalize
+pub fn canonicalize_in_sysroot(sysroot: Option<&Path>, path: &Path) -> std::io::Result<PathBuf> {
+    let (root, relative) = match sysroot.and_then(|root| Some((root, path.strip_prefix(root).ok()?))) {
+        Some((root, relative)) => (std::fs::canonicalize(root)?, relative),
+        None => return std::fs::canonicalize(path),
+    };
+    // the components that are left to resolve, in reverse, so that a symlink's target can be pushed
+    let mut pending: Vec<OsString> = relative.iter().rev().map(|c| c.to_os_string()).collect();
+    let mut resolved = PathBuf::new();
+    let mut links_followed = 0;
+    while let Some(part) = pending.pop() {
+        if part == "." || part == "/" {
+            continue;
+        }
+        if part == ".." {
+            resolved.pop();
+            continue;
+        }
+        let candidate = resolved.join(&part);
+        let full = root.join(&candidate);
+        if !std::fs::symlink_metadata(&full)?.file_type().is_symlink() {
+            resolved = candidate;
+            continue;
+        }
+        links_followed += 1;
+        if links_followed > 40 {
+            return Err(std::io::Error::other(format!("Too many levels of symbolic links at {:?}", full)));
+        }
+        let target = std::fs::read_link(&full)?;
+        if target.is_absolute() {
+            resolved = PathBuf::new();
+        }
+        pending.extend(target.iter().rev().map(|c| c.to_os_string()));
+    }
+    Ok(root.join(resolved))
+}
+
+/// makes the output path absolute, with its parent directory canonicalized, so that
+/// eg: ./out and out are the same path. the output itself might not exist yet, so only
+/// its parent is canonicalized, and only if the parent exists
+pub fn normalize_output_path(path: &Path) -> Result<PathBuf, Error> {
+    let name = path.file_name()
+        .ok_or_else(|| Error::Other(format!("Output {:?} does not end in a file name", path)))?;
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let parent = match std::fs::canonicalize(parent) {
+        Ok(p) => p,
+        Err(_) => std::env::current_dir()
+            .map_err(|e| Error::Io(format!("Failed to get the current directory\n{}", e)))?
+            .join(parent),
+    };
+    Ok(parent.join(name))
+}
+
+/// the output path with -stamp appended to its name, eg: out-1a2b3c. the stamp
+/// goes before a .squashfs extension, eg: out-1a2b3c.squashfs
+pub fn stamp_output_path(path: &Path, stamp: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    match name.strip_suffix(".squashfs") {
+        Some(stem) => path.with_file_name(format!("{}-{}.squashfs", stem, stamp)),
+        None => path.with_file_name(format!("{}-{}", name, stamp)),
+    }
+}
+
+/// the current UTC time, for stamping an output with, eg: 20240131T235959Z
+pub fn time_stamp() -> Result<String, Error> {
+    let output = execute_checked(&["date", "-u", "+%Y%m%dT%H%M%SZ"])?;
+    if output.status != 0 {
+        return Err(Error::Other(format!("Failed to get the current time\n{}", output.stderr)));
+    }
+    Ok(output.stdout.trim().to_string())
+}
+
+/// fails if the output is, or contains, the directory of any of the source files, so
+/// that packing can never copy a file onto itself or overwrite the libs of the host
+pub fn check_output_is_not_a_source_dir<'a>(
+    output: &Path,
+    sources: impl IntoIterator<Item = &'a Path>,
+) -> Result<(), Error> {
+    let output = std::fs::canonicalize(output).unwrap_or_else(|_| output.to_path_buf());
+    for source in sources {
+        let source_dir = match std::fs::canonicalize(source).ok().as_deref().and_then(Path::parent) {
+            Some(dir) => dir.to_path_buf(),
+            None => continue,
+        };
+        if source_dir.starts_with(&output) {
+            return Err(Error::Other(format!(
+                "Refusing to pack into {:?}, because it contains {:?}, which is where {:?} is copied from",
+                output, source_dir, source)));
+        }
+    }
+    Ok(())
+}
+
+/// creates the output directory along with any missing parents, with an error that names
+/// the output and why it could not be created, eg: one of its parents is a file
+pub fn create_output_dir(output: &Path) -> Result<(), Error> {
+    std::fs::create_dir_all(output).map_err(|e| {
+        // the closest ancestor that exists is the one that create_dir_all stopped at
+        let blocking = output.ancestors().find(|a| a.symlink_metadata().is_ok());
+        match blocking {
+            Some(ancestor) if !ancestor.is_dir() => Error::Io(format!(
+                "Cannot create the output {:?}, because {:?} is a file, not a directory", output, ancestor)),
+            _ => Error::Io(format!("Cannot create the output {:?}\n{}", output, e)),
+        }
+    })
+}
+
+/// makes sure files can be written into output, by creating a probe file in it. if
+/// output does not exist yet, it is created for the probe and then removed again, along
+/// with any of its parents that had to be created
+pub fn check_output_writable(output: &Path) -> Result<(), Error> {
+    let mut first_created = None;
+    let mut ancestor = output;
+    while ancestor.symlink_metadata().is_err() {
+        first_created = Some(ancestor);
+        ancestor = match ancestor.parent() {
+            Some(p) => p,
+            None => break,
+        };
+    }
+    let probe = || -> Result<(), Error> {
+        create_output_dir(output)?;
+        let probe_path = output.join(format!(".sharedpacker-probe-{}", std::process::id()));
+        std::fs::write(&probe_path, "")
+            .map_err(|e| Error::Io(format!("Cannot write into the output {:?}\n{}", output, e)))?;
+        let _ = std::fs::remove_file(&probe_path);
+        Ok(())
+    };
+    let result = probe();
+    if let Some(created) = first_created {
+        let _ = std::fs::remove_dir_all(created);
+    }
+    result
+}
+
+/// makes sure a source file can be opened and read
+pub fn check_readable(path: &Path) -> Result<(), Error> {
+    use std::io::Read;
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| Error::Io(format!("Cannot open {:?}\n{}", path, e)))?;
+    file.read(&mut [0u8; 1])
+        .map_err(|e| Error::Io(format!("Cannot read {:?}\n{}", path, e)))?;
+    Ok(())
+}
+
+/// the directories of every RemoveOnDrop that has not been dropped yet
+static PENDING_REMOVALS: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+
+/// a directory that is removed, along with everything in it, once this is dropped,
+/// or by remove_pending_dirs if the process exits before that, eg: on Ctrl-C
+pub struct RemoveOnDrop(PathBuf);
+
+impl RemoveOnDrop {
+    pub fn new(path: PathBuf) -> RemoveOnDrop {
+        PENDING_REMOVALS.lock().unwrap_or_else(|e| e.into_inner()).push(path.clone());
+        RemoveOnDrop(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+        PENDING_REMOVALS.lock().unwrap_or_else(|e| e.into_inner()).retain(|p| *p != self.0);
+    }
+}
+
+/// removes the directory of every RemoveOnDrop that is still alive, for when
+/// the process exits without dropping them, eg: with std::process::exit
+pub fn remove_pending_dirs() {
+    for path in PENDING_REMOVALS.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
+
+/// recursively collects every file under dir as a path relative to base
+pub fn list_files_relative(
+    base: &Path,
+    dir: &Path,
+    outvec: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| Error::Io(format!("Failed to read directory {:?}\n{}", dir, e)))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| Error::Io(e.to_string()))?;
+        let path = entry.path();
+        if path.is_dir() {
+            list_files_relative(base, &path, outvec)?;
+        } else if let Ok(relative) = path.strip_prefix(base) {
+            outvec.push(relative.into());
+        }
+    }
+    Ok(())
+}
+
+/// the mtime that --reproducible gives every file: SOURCE_DATE_EPOCH if it
+/// is set, see https://reproducible-builds.org/specs/source-date-epoch/, or else 0
+pub fn reproducible_epoch() -> Result<u64, Error> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) if !epoch.is_empty() => epoch.trim().parse()
+            .map_err(|_| Error::Other(format!("SOURCE_DATE_EPOCH must be a number of seconds, but got {:?}", epoch))),
+        _ => Ok(0),
+    }
+}
+
+/// sets the mtime of dir and of everything under it, including symlinks themselves, to epoch
+pub fn set_mtimes(dir: &Path, epoch: u64) -> Result<(), Error> {
+    let dir_str = dir.to_string_lossy();
+    let date = format!("@{}", epoch);
+    let exec_args = ["find", &dir_str, "-exec", "touch", "-h", "-d", &date, "{}", "+"];
+    let out = execute_checked(&exec_args)?;
+    if out.status != 0 {
+        return Err(Error::Io(format!("Failed to set the mtimes under {:?}\n{}", dir, out.stderr)));
+    }
+    Ok(())
+}
+
+/// writes archive_path/SHA256SUMS in the format that sha256sum -c expects.
+/// the checksums file itself is not listed
+pub fn write_checksums_file(archive_path: &Path) -> Result<(), Error> {
+    let mut files = vec![];
+    list_files_relative(archive_path, archive_path, &mut files)?;
+    files.sort();
+
+    let mut out = String::new();
+    for file in files {
+        if file == Path::new("SHA256SUMS") {
+            continue;
+        }
+        let hash = sha256_file(&archive_path.join(&file))?;
+        out.push_str(&format!("{}  {}\n", hash, file.to_string_lossy()));
+    }
+
+    let sums_path = archive_path.join("SHA256SUMS");
+    std::fs::write(&sums_path, out)
+        .map_err(|e| Error::Io(format!("Failed to write {:?}\n{}", sums_path, e)))
+}
+
+/// checks for the ELF magic bytes at the start of the file
+pub fn is_elf_file(path: &Path) -> bool {
+    use std::io::Read;
+    let mut magic = [0u8; 4];
+    match std::fs::File::open(path) {
+        Ok(mut f) => f.read_exact(&mut magic).is_ok() && magic == *b"\x7fELF",
+        Err(_) => false,
+    }
+}
+
+/// reads one executable path per line from the given file, or from
+/// stdin if the path is "-". blank lines and # comments are skipped
+pub fn read_exepaths_from_file(
+    path: &Path,
+) -> Result<Vec<PathBuf>, Error> {
+    let reader: Box<dyn BufRead> = if path == Path::new("-") {
+        Box::new(std::io::BufReader::new(std::io::stdin()))
+    } else {
+        let file = std::fs::File::open(path)
+            .map_err(|e| Error::Io(format!("Failed to open {:?}\n{}", path, e)))?;
+        Box::new(std::io::BufReader::new(file))
+    };
+
+    let mut outvec = vec![];
+    for line in reader.lines() {
+        let line = line.map_err(|e| Error::Io(format!("Failed to read {:?}\n{}", path, e)))?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        outvec.push(trimmed.into());
+    }
+
+    Ok(outvec)
+}
+
+/// replaces each directory in exepaths with the executable ELF files directly in it,
+/// or with recursive, anywhere under it. files that are not ELF are skipped, with a
+/// note if verbose. paths that are not directories are kept as they are
+pub fn expand_exe_dirs(
+    exepaths: &[PathBuf],
+    recursive: bool,
+    verbose: bool,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut outvec = vec![];
+    for path in exepaths {
+        if path.is_dir() {
+            find_executables(path, recursive, verbose, &mut outvec)?;
+        } else {
+            outvec.push(path.clone());
+        }
+    }
+    Ok(outvec)
+}
+
+fn find_executables(
+    dir: &Path,
+    recursive: bool,
+    verbose: bool,
+    outvec: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| Error::Io(format!("Failed to read directory {:?}\n{}", dir, e)))?;
+    let mut paths = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|e| Error::Io(format!("Failed to read directory {:?}\n{}", dir, e)))?;
+        paths.push(entry.path());
+    }
+    // sorted so that the executables are always packed in the same order
+    paths.sort();
+    for path in paths {
+        let metadata = match std::fs::metadata(&path) {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        if metadata.is_dir() {
+            if recursive {
+                find_executables(&path, recursive, verbose, outvec)?;
+            }
+        } else if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
+            if is_elf_file(&path) {
+                outvec.push(path);
+            } else if verbose {
+                eprintln!("Skipping {:?} because it is not an ELF file", path);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// names the permission bits of a file that are unusual for a library
+/// and might point at tampering or a packaging mistake
+pub fn unusual_permissions(path: &Path) -> Vec<&'static str> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = match std::fs::metadata(path) {
+        Ok(m) => m.permissions().mode(),
+        Err(_) => return vec![],
+    };
+    let mut found = vec![];
+    if mode & 0o4000 != 0 {
+        found.push("setuid");
+    }
+    if mode & 0o2000 != 0 {
+        found.push("setgid");
+    }
+    if mode & 0o002 != 0 {
+        found.push("world-writable");
+    }
+    found
+}
+
+/// prints a warning to stderr if the file has any unusual_permissions
+pub fn warn_unusual_permissions(path: &Path) {
+    let found = unusual_permissions(path);
+    if !found.is_empty() {
+        eprintln!("Warning: {:?} is {}", path, found.join(", "));
+    }
+}
+
+/// parses an octal file mode like 755 or 0750
+pub fn parse_mode(s: &str) -> Result<u32, Error> {
+    let trimmed = s.trim();
+    match u32::from_str_radix(trimmed, 8) {
+        Ok(mode) if mode <= 0o7777 && !trimmed.starts_with('+') => Ok(mode),
+        _ => Err(Error::Other(format!("Invalid mode {:?}, expected an octal mode like 0755", s))),
+    }
+}
+
+/// sets the permission bits of the file to mode, like chmod
+pub fn set_mode(path: &Path, mode: u32) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .map_err(|e| Error::Io(format!("Failed to set the mode of {:?} to {:o}\n{}", path, mode, e)))
+}
+
+/// parses a size like 1048576, 512K, 1.5M, 2GiB or 10MB. the suffixes
+/// are all powers of 1024
+pub fn parse_size(s: &str) -> Result<u64, Error> {
+    let trimmed = s.trim();
+    let split_at = trimmed.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(trimmed.len());
+    let (number, suffix) = trimmed.split_at(split_at);
+    let number: f64 = number.parse()
+        .map_err(|_| Error::Other(format!("Invalid size {:?}, expected something like 500M", s)))?;
+    let multiplier: u64 = match suffix.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(Error::Other(format!("Invalid size suffix in {:?}, expected one of K, M, G, T", s))),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
@@ -0,0 +1,112 @@
+use std::{path::{Path, PathBuf}, collections::HashMap};
+use crate::deps::DependencyNode;
+
+/// matches a shell style glob where * is any run of characters
+/// and ? is any single character. there are no character classes
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // where the last * was, and how much of the text it has eaten
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // let the last * eat one more character and try again
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// whether a lib name matches any of the --exclude globs
+pub fn is_excluded(name: &str, exclude: &[String]) -> bool {
+    exclude.iter().any(|pattern| glob_match(pattern, name))
+}
+
+/// the names that match none of the --allow globs. with no globs, everything is allowed
+pub fn find_disallowed<'a>(names: impl IntoIterator<Item = &'a str>, allow: &[String]) -> Vec<String> {
+    if allow.is_empty() {
+        return vec![];
+    }
+    names.into_iter()
+        .filter(|name| !is_excluded(name, allow))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// whether a lib's source path is under any of the --exclude-path directories.
+/// both sides are compared after following symlinks
+pub fn is_under_excluded_path(path: &Path, exclude_paths: &[PathBuf]) -> bool {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    exclude_paths.iter().any(|dir| {
+        let dir = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.clone());
+        path.starts_with(dir)
+    })
+}
+
+/// whether the --exclude globs or the --exclude-path directories leave out dep
+pub fn excludes(dep: &DependencyNode, exclude: &[String], exclude_paths: &[PathBuf]) -> bool {
+    is_excluded(&dep.name, exclude) || is_under_excluded_path(&dep.path, exclude_paths)
+}
+
+/// the version at the end of a lib's file name, after following symlinks,
+/// eg: 3.0.2 for libssl.so.3 when it links to libssl.so.3.0.2. None without one
+pub fn lib_file_version(path: &Path) -> Option<String> {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let filename = path.file_name()?.to_string_lossy().to_string();
+    let (_, version) = filename.split_once(".so.")?;
+    Some(version.to_string())
+}
+
+/// decides for each lib whether to bundle it: true to bundle it, false to leave it out.
+/// this is a Fn + Send + Sync rather than a FnMut, because the CopyOptions holding it are
+/// shared by reference with the copy_jobs and patch_jobs threads, and because it can be
+/// asked about the same lib more than once, eg: with mirror_paths. any state it keeps
+/// has to be behind a Mutex or similar
+pub struct LibFilter(pub Box<dyn Fn(&DependencyNode) -> bool + Send + Sync>);
+
+impl LibFilter {
+    pub fn new(predicate: impl Fn(&DependencyNode) -> bool + Send + Sync + 'static) -> LibFilter {
+        LibFilter(Box::new(predicate))
+    }
+
+    /// the filter of the --exclude and --exclude-path flags
+    pub fn excluding(exclude: Vec<String>, exclude_paths: Vec<PathBuf>) -> LibFilter {
+        LibFilter::new(move |dep| !excludes(dep, &exclude, &exclude_paths))
+    }
+
+    /// keeps a lib only if every one of filters keeps it
+    pub fn all(filters: Vec<LibFilter>) -> LibFilter {
+        LibFilter::new(move |dep| filters.iter().all(|filter| filter.keeps(dep)))
+    }
+
+    /// the filter of --target-inventory: leaves out the libs that the target already has.
+    /// a lib listed with a version is only left out when lib_file_version matches it
+    pub fn missing_from(inventory: HashMap<String, Option<String>>) -> LibFilter {
+        LibFilter::new(move |dep| match inventory.get(&dep.name) {
+            Some(Some(version)) => lib_file_version(&dep.path).as_ref() != Some(version),
+            Some(None) => false,
+            None => true,
+        })
+    }
+
+    pub fn keeps(&self, dep: &DependencyNode) -> bool {
+        (self.0)(dep)
+    }
+}
+
+impl std::fmt::Debug for LibFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LibFilter")
+    }
+}
@@ -0,0 +1,53 @@
+use std::path::{Path, PathBuf};
+use crate::Error;
+use crate::tools::execute_checked;
+
+/// what glibc's libc is needed as
+pub const LIBC_NAME: &str = "libc.so.6";
+
+/// the directory the gconv modules are copied to in the archive, which the wrapper sets GCONV_PATH to
+pub const GCONV_DIR_NAME: &str = "gconv";
+
+/// the NSS modules next to libc, eg: libnss_dns.so.2. glibc dlopens these by name to
+/// look up hosts and users, so they never show up in ldd. only the versioned
+/// files are returned, the unversioned libnss_*.so ones are for linking against
+pub fn find_nss_libs(libc_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = std::fs::read_dir(libc_dir)
+        .map_err(|e| Error::Io(format!("Failed to read directory {:?}\n{}", libc_dir, e)))?;
+    let mut nss_libs = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|e| Error::Io(format!("Failed to read directory {:?}\n{}", libc_dir, e)))?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with("libnss_") && name.contains(".so.") && entry.path().is_file() {
+            nss_libs.push(entry.path());
+        }
+    }
+    nss_libs.sort();
+    Ok(nss_libs)
+}
+
+/// the directory of iconv's gconv modules for the glibc in libc_dir, if it has one.
+/// glibc loads these by path from GCONV_PATH, or else from where it was built to look
+pub fn find_gconv_dir(libc_dir: &Path) -> Option<PathBuf> {
+    let gconv_dir = libc_dir.join("gconv");
+    if gconv_dir.join("gconv-modules").is_file() || gconv_dir.join("gconv-modules.d").is_dir() {
+        Some(gconv_dir)
+    } else { None }
+}
+
+/// copies the gconv modules as they are to archive_path/GCONV_DIR_NAME, replacing any that are
+/// already there. they are only ever dlopened by a libc that is already loaded, so they need no patching
+pub fn copy_gconv_modules(gconv_dir: &Path, archive_path: &Path) -> Result<(), Error> {
+    let dest = archive_path.join(GCONV_DIR_NAME);
+    if dest.symlink_metadata().is_ok() {
+        std::fs::remove_dir_all(&dest)
+            .map_err(|e| Error::Io(format!("Failed to remove {:?}\n{}", dest, e)))?;
+    }
+    let src_str = gconv_dir.to_string_lossy();
+    let dest_str = dest.to_string_lossy();
+    let out = execute_checked(&["cp", "-a", &src_str, &dest_str])?;
+    if out.status != 0 {
+        return Err(Error::Io(format!("Failed to copy {:?} to {:?}\n{}", gconv_dir, dest, out.stderr)));
+    }
+    Ok(())
+}
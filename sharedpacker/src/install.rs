@@ -0,0 +1,165 @@
+use std::{path::{Path, PathBuf}, time::{Duration, Instant}};
+use crate::Error;
+use crate::tools::execute_checked;
+use crate::wrapper::shell_escape_double_quoted;
+
+/// an exclusive lock on an install location, held for as long as this is alive.
+/// the lock is a file next to the location that only one process can create
+pub struct InstallLock {
+    path: PathBuf,
+}
+
+impl InstallLock {
+    /// waits up to timeout for any other install to the same location to finish
+    pub fn acquire(install_path: &Path, timeout: Duration) -> Result<InstallLock, Error> {
+        let path = sibling_path(install_path, "lock")?;
+        let started = Instant::now();
+        loop {
+            match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => {
+                    let _ = std::fs::write(&path, std::process::id().to_string());
+                    return Ok(InstallLock { path });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if started.elapsed() > timeout {
+                        return Err(Error::Io(format!(
+                            "Timed out waiting for another install to {:?} to finish. if none is running, remove {:?}",
+                            install_path, path)));
+                    }
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => return Err(Error::Io(format!("Failed to create lock file {:?}\n{}", path, e))),
+            }
+        }
+    }
+}
+
+impl Drop for InstallLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// a hidden path in the same directory as path, so that renaming
+/// between the two never has to cross a filesystem
+pub fn sibling_path(path: &Path, what: &str) -> Result<PathBuf, Error> {
+    let name = path.file_name()
+        .ok_or_else(|| Error::Other(format!("{:?} does not end in a directory name", path)))?
+        .to_string_lossy().to_string();
+    Ok(path.with_file_name(format!(".{}.{}", name, what)))
+}
+
+/// renames staging_path to path, replacing whatever was there before. the previous
+/// one is only removed once the new one is in place, and is put back if that fails
+pub fn move_into_place(staging_path: &Path, path: &Path) -> Result<(), Error> {
+    let old_path = sibling_path(path, &format!("old-{}", std::process::id()))?;
+    let had_previous = path.symlink_metadata().is_ok();
+    if had_previous {
+        std::fs::rename(path, &old_path)
+            .map_err(|e| Error::Io(format!("Failed to move the previous {:?} aside\n{}", path, e)))?;
+    }
+    if let Err(e) = std::fs::rename(staging_path, path) {
+        // put the previous one back so the location isnt left empty
+        if had_previous {
+            let _ = std::fs::rename(&old_path, path);
+        }
+        return Err(Error::Io(format!("Failed to move {:?} into place at {:?}\n{}", staging_path, path, e)));
+    }
+    if had_previous {
+        std::fs::remove_dir_all(&old_path)
+            .map_err(|e| Error::Io(format!("Moved {:?} into place, but failed to remove the previous one at {:?}\n{}", path, old_path, e)))?;
+    }
+    Ok(())
+}
+
+/// copies the archive next to install_path, and then renames it into place,
+/// replacing whatever was installed there before. this all happens under an
+/// InstallLock so that concurrent installs to the same location dont mix their files.
+/// the previous install is only removed once the new one is in place
+pub fn install_archive(
+    archive_path: &Path,
+    install_path: &Path,
+    lock_timeout: Duration,
+) -> Result<(), Error> {
+    if let Some(parent) = install_path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| Error::Io(format!("Failed to create {:?}\n{}", parent, e)))?;
+    }
+    let _lock = InstallLock::acquire(install_path, lock_timeout)?;
+
+    let staging_path = sibling_path(install_path, &format!("installing-{}", std::process::id()))?;
+    let _ = std::fs::remove_dir_all(&staging_path);
+
+    // cp -a so symlinks like the loader symlink and the executable bits are kept
+    let archive_str = archive_path.to_string_lossy();
+    let staging_str = staging_path.to_string_lossy();
+    let out = execute_checked(&["cp", "-a", &archive_str, &staging_str])?;
+    if out.status != 0 {
+        let _ = std::fs::remove_dir_all(&staging_path);
+        return Err(Error::Io(format!("Failed to copy {:?} to {:?}\n{}", archive_path, staging_path, out.stderr)));
+    }
+
+    move_into_place(&staging_path, install_path).inspect_err(|_| {
+        let _ = std::fs::remove_dir_all(&staging_path);
+    })
+}
+
+/// name of the installer script that make_install_script is written to in the output
+pub const INSTALL_SCRIPT_NAME: &str = "install.sh";
+
+/// an install.sh for the output that copies the bundle to <prefix>/lib/<bundle_name> and
+/// links each of the wrappers from <prefix>/bin. everything is checked before anything is
+/// changed, so it either installs fully or not at all. running it again with the same
+/// bundle is a no-op, but it refuses to replace anything else unless given --force
+pub fn make_install_script(bundle_name: &str, wrapper_names: &[String]) -> String {
+    let wrappers: Vec<String> = wrapper_names.iter()
+        .map(|w| format!("\"{}\"", shell_escape_double_quoted(w))).collect();
+    format!(r#"#!/usr/bin/env bash
+# installer made by sharedpacker
+set -e
+usage() {{ echo "usage: $0 [--force] <prefix>" >&2; exit 1; }}
+FORCE=0
+PREFIX=""
+for arg in "$@"; do
+    case "$arg" in
+        --force) FORCE=1 ;;
+        -*) usage ;;
+        *) [ -z "$PREFIX" ] || usage; PREFIX="$arg" ;;
+    esac
+done
+[ -n "$PREFIX" ] || usage
+BUNDLE_DIR="$( cd -- "$(dirname "$0")" >/dev/null 2>&1 ; pwd -P )"
+DEST="$PREFIX/lib/{bundle}"
+WRAPPERS=({wrappers})
+
+COPY=1
+if [ -e "$DEST" ] || [ -L "$DEST" ]; then
+    if diff -r -q --no-dereference "$BUNDLE_DIR" "$DEST" >/dev/null 2>&1; then
+        COPY=0
+    elif [ "$FORCE" = 0 ]; then
+        echo "$DEST already exists and differs from this bundle. pass --force to replace it" >&2
+        exit 1
+    fi
+fi
+for wrapper in "${{WRAPPERS[@]}}"; do
+    link="$PREFIX/bin/$wrapper"
+    if [ -e "$link" ] || [ -L "$link" ]; then
+        if [ "$(readlink "$link")" != "$DEST/$wrapper" ] && [ "$FORCE" = 0 ]; then
+            echo "$link already exists. pass --force to replace it" >&2
+            exit 1
+        fi
+    fi
+done
+
+if [ "$COPY" = 1 ]; then
+    mkdir -p "$PREFIX/lib"
+    rm -rf "$DEST"
+    cp -a "$BUNDLE_DIR" "$DEST"
+fi
+mkdir -p "$PREFIX/bin"
+for wrapper in "${{WRAPPERS[@]}}"; do
+    ln -sfn "$DEST/$wrapper" "$PREFIX/bin/$wrapper"
+done
+echo "Installed to $DEST"
+"#, bundle = shell_escape_double_quoted(bundle_name), wrappers = wrappers.join(" "))
+}
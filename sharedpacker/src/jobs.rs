@@ -0,0 +1,25 @@
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::Error;
+
+/// one entry of a --jobs-file. the cli flags apply to every job,
+/// and the job's own fields are applied on top of them
+#[derive(Debug, Clone, Deserialize)]
+pub struct Job {
+    pub exe: PathBuf,
+    pub output: PathBuf,
+    /// overrides --make-wrapper for this job
+    #[serde(default)]
+    pub make_wrapper: Option<bool>,
+    /// globs of lib names to leave out, added to any --exclude
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// parses a --jobs-file, which is a JSON array of job objects
+pub fn read_jobs_file(path: &Path) -> Result<Vec<Job>, Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| Error::Io(format!("Failed to read {:?}\n{}", path, e)))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| Error::Other(format!("Failed to parse jobs file {:?}: {}", path, e)))
+}
@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+use crate::Error;
+use crate::tools::{ToolConfig, execute_tool};
+use crate::elftool::ElfTool;
+
+#[derive(Debug, Clone)]
+pub struct SharedLib {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// runs ldd on path and returns its stdout
+fn run_ldd(
+    tools: &ToolConfig,
+    path: &Path,
+) -> Result<String, Error> {
+    let strthing: &str = path.to_str().map_or_else(|| Err("Failed to get path as string"), Ok)?;
+    let exec_args = [
+        &tools.ldd, strthing,
+    ];
+    let output = execute_tool(tools, &exec_args)?;
+    if output.status != 0 {
+        return Err(Error::Other(output.stderr));
+    }
+    Ok(output.stdout)
+}
+
+/// the libs that ldd reports for path, not including the loader
+pub fn parse_ldd_output(
+    tools: &ToolConfig,
+    path: &Path,
+) -> Result<Vec<SharedLib>, Error> {
+    // eprintln!("GOT OUTPUT: \n{}", output.stdout);
+    parse_ldd_lines(&run_ldd(tools, path)?)
+}
+
+/// the file name part of a path as reported by ldd
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// one line of ldd output that names a lib
+enum LddEntry {
+    /// name => path
+    Named(SharedLib),
+    /// an entry whose name is an absolute path. this is usually just the loader,
+    /// but a DT_NEEDED entry can also be an absolute path, eg: if a lib without
+    /// a soname was linked in by its full path
+    Absolute(SharedLib),
+    /// name => not found
+    NotFound(String),
+}
+
+/// the entries of an ldd invocation's stdout, in the order ldd printed them
+fn parse_ldd_entries(ldd_stdout: &str) -> Result<Vec<LddEntry>, Error> {
+    let mut entries = vec![];
+
+    // rules for parsing ldd output:
+    // - must start with at least one empty whitespace char
+    //   because its possible ldd might display some header info that
+    //   we dont want to parse
+    // - must contain an arrow '=>' otherwise it is something thats statically linked?
+    //   unless its name is an absolute path, which ldd prints without the arrow
+    // - must not be a diagnostic from ldd itself like "ldd: warning: ..." which
+    //   could otherwise contain an arrow in its message
+    for line in ldd_stdout.lines() {
+        if !line.starts_with(' ') && !line.starts_with('\t') {
+            continue;
+        }
+        let no_whitespace = line.trim_start().trim_end();
+        if no_whitespace.starts_with("ldd:") {
+            continue;
+        }
+        if !no_whitespace.contains(" => ") {
+            // the loader and any absolute path needed libs are present without
+            // the => mapping, as just their full path
+            if no_whitespace.starts_with('/') {
+                let abs_path = match no_whitespace.split(' ').next() {
+                    Some(p) => p,
+                    None => continue,
+                };
+                entries.push(LddEntry::Absolute(SharedLib {
+                    name: basename(abs_path).into(),
+                    path: abs_path.into(),
+                }));
+            }
+
+            continue;
+        }
+
+        let mut split = no_whitespace.split(" => ");
+        let libname = split.next().map_or_else(|| Err("Failed to parse ldd output"), Ok)?;
+        let pathpart = split.next().map_or_else(|| Err("Failed to parse ldd output"), Ok)?;
+        if pathpart.contains("not found") {
+            entries.push(LddEntry::NotFound(libname.into()));
+            continue;
+        }
+
+        // some ldd versions print kernel provided objects like the vdso with an
+        // arrow but no real path, eg: "linux-gate.so.1 =>  (0xb7f6c000)"
+        let pathpart = pathpart.trim_start();
+        if pathpart.is_empty() || pathpart.starts_with('(') {
+            continue;
+        }
+
+        let pathpart = match pathpart.find(' ') {
+            None => pathpart,
+            Some(index) => {
+                &pathpart[0..index]
+            }
+        };
+
+        // some ldd versions print the loader, or an absolute path needed lib,
+        // with its full path on the left of the arrow
+        if libname.starts_with('/') {
+            entries.push(LddEntry::Absolute(SharedLib {
+                name: basename(libname).into(),
+                path: pathpart.into(),
+            }));
+            continue;
+        }
+
+        entries.push(LddEntry::Named(SharedLib {
+            name: libname.into(),
+            path: pathpart.into(),
+        }));
+    }
+    Ok(entries)
+}
+
+/// which of the absolute entries is the loader. when there are absolute path needed
+/// libs as well, the loader is the one named like ld-linux-x86-64.so.2 or ld-musl-x86_64.so.1.
+/// otherwise its the last one, because ldd prints the loader after the needed libs
+fn loader_index(absolute_entries: &[SharedLib]) -> Option<usize> {
+    absolute_entries.iter().position(|lib| lib.name.starts_with("ld-"))
+        .or_else(|| absolute_entries.len().checked_sub(1))
+}
+
+/// parses the stdout of an ldd invocation into the list of libs, leaving out the
+/// loader. fails on the first lib that ldd could not find
+pub fn parse_ldd_lines(ldd_stdout: &str) -> Result<Vec<SharedLib>, Error> {
+    let mut outvec = vec![];
+    let mut absolute_entries = vec![];
+    for entry in parse_ldd_entries(ldd_stdout)? {
+        match entry {
+            LddEntry::Named(lib) => outvec.push(lib),
+            LddEntry::Absolute(lib) => absolute_entries.push(lib),
+            LddEntry::NotFound(name) => {
+                return Err(Error::MissingDependency(format!("Dependency on {} is not found", name)));
+            }
+        }
+    }
+    let loader_index = loader_index(&absolute_entries);
+    for (i, lib) in absolute_entries.into_iter().enumerate() {
+        if Some(i) != loader_index {
+            outvec.push(lib);
+        }
+    }
+    Ok(outvec)
+}
+
+/// the loader entry in the stdout of an ldd invocation, if there is one.
+/// a missing lib doesnt stop us from finding the loader
+pub fn parse_ldd_loader_line(ldd_stdout: &str) -> Result<Option<SharedLib>, Error> {
+    let mut absolute_entries: Vec<SharedLib> = parse_ldd_entries(ldd_stdout)?.into_iter()
+        .filter_map(|entry| match entry {
+            LddEntry::Absolute(lib) => Some(lib),
+            _ => None,
+        })
+        .collect();
+    Ok(loader_index(&absolute_entries).map(|i| absolute_entries.swap_remove(i)))
+}
+
+/// the loader that ldd reports for path, if it reports one
+pub fn ldd_loader(
+    tools: &ToolConfig,
+    path: &Path,
+) -> Result<Option<SharedLib>, Error> {
+    parse_ldd_loader_line(&run_ldd(tools, path)?)
+}
+
+/// the interpreter of an executable, see ElfTool::interpreter, named by its basename.
+/// None if the file has no interpreter, eg: a shared lib or a static executable
+pub fn detect_interpreter(
+    tools: &dyn ElfTool,
+    path: &Path,
+) -> Result<Option<SharedLib>, Error> {
+    Ok(tools.interpreter(path)?.map(|interpreter| SharedLib {
+        name: basename(&interpreter).to_string(),
+        path: interpreter.into(),
+    }))
+}
+
+/// the loader of an executable, see detect_interpreter. fails if it has none
+pub fn get_loader(
+    tools: &dyn ElfTool,
+    path: &Path,
+) -> Result<SharedLib, Error> {
+    match detect_interpreter(tools, path)? {
+        Some(lib) => Ok(lib),
+        None => Err(Error::Other(format!("Failed to get loader from {:?}, it has no interpreter", path))),
+    }
+}
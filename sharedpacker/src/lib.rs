@@ -0,0 +1,26 @@
+pub mod error;
+pub mod tools;
+pub mod ldd;
+pub mod deps;
+pub mod patch;
+pub mod wrapper;
+pub mod copy;
+pub mod files;
+pub mod sbom;
+pub mod check;
+pub mod elf;
+pub mod upx;
+pub mod archive;
+pub mod filter;
+pub mod jobs;
+pub mod install;
+pub mod color;
+pub mod desktop;
+pub mod report;
+pub mod events;
+pub mod glibc;
+pub mod doctor;
+pub mod options;
+pub mod elftool;
+
+pub use error::Error;
@@ -1,417 +1,1330 @@
-use exechelper;
 use gumdrop::Options;
-use std::{path::{Path, PathBuf}, collections::HashMap};
+use serde::{Serialize, Deserialize};
+use std::{path::{Path, PathBuf}, collections::HashMap, time::{Duration, Instant}, sync::Mutex};
+use sharedpacker::{Error, color, events};
+use sharedpacker::tools::{ToolConfig, validate_tool_path, execute_checked};
+use sharedpacker::deps::{DependencyNode, ResolveOptions, TraverseOptions, map_lib, loader_names, traverse_dependencies, find_unreachable, dedupe_by_path, resolve_loader, resolve_dependencies, check_single_loader, prune_duplicate_sonames};
+use sharedpacker::ldd::SharedLib;
+use sharedpacker::wrapper::{WrapperOptions, parse_env_pair, is_valid_env_name};
+use sharedpacker::copy::{CopyOptions, PatchStrategy, cleanup_if_err, copy_dependencies_to_output_folder};
+use sharedpacker::files::{read_exepaths_from_file, expand_exe_dirs, reproducible_epoch, set_mtimes, write_checksums_file, normalize_output_path, parse_size, check_output_is_not_a_source_dir, check_output_writable, check_readable, RemoveOnDrop, remove_pending_dirs, parse_mode, stamp_output_path, time_stamp};
+use sharedpacker::sbom::{make_sbom, read_base_manifest, read_target_inventory};
+use sharedpacker::check::{check_closure, check_bundle, verify_runnable, resolve_in_bundle};
+use sharedpacker::elf::{check_architectures, read_build_id};
+use sharedpacker::upx::{check_upx_installed, compress_files};
+use sharedpacker::archive::{OutputFormat, check_mksquashfs_installed, make_squashfs, make_targz, make_self_extracting, write_tar_to_stdout};
+use sharedpacker::desktop::make_desktop_entry;
+use sharedpacker::report::{LibRecord, LibStatus, make_report_json, Summary, make_summary_json};
+use sharedpacker::glibc::{LIBC_NAME, GCONV_DIR_NAME, find_nss_libs, find_gconv_dir, copy_gconv_modules};
+use sharedpacker::filter::{LibFilter, excludes, find_disallowed};
+use sharedpacker::jobs::read_jobs_file;
+use sharedpacker::doctor::{OPTIONAL_TOOLS, check_tools};
+use sharedpacker::options::{read_options_json, overlay_json_options};
+use sharedpacker::install::{INSTALL_SCRIPT_NAME, install_archive, make_install_script, sibling_path, move_into_place};
+
+/// the subcommands. when the first argument isnt one of these, pack is assumed,
+/// so that sharedpacker <exe> keeps working as it did before there were subcommands
+#[derive(Debug, Options)]
+#[allow(clippy::large_enum_variant)]
+pub enum Command {
+    /// list the libs and the loader that executables need, without copying anything
+    Deps(DepsCli),
+    /// bundle executables with their libs and loader into an output folder
+    Pack(PackCli),
+    /// validate the interpreters, rpaths and needed libs of the files in a bundle that was already built
+    Check(CheckCli),
+    /// check that ldd, patchelf and the other tools that packing runs are installed and recent enough
+    Doctor(DoctorCli),
+}
 
 #[derive(Debug, Options)]
 pub struct Cli {
     /// prints the help
     pub help: bool,
 
+    /// prints the version and exits
+    #[options(short = "V")]
+    pub version: bool,
+
+    #[options(command)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Options)]
+pub struct DepsCli {
+    /// prints the help
+    pub help: bool,
+
+    /// print detailed logging info to stderr
+    pub verbose: bool,
+
+    /// number of seconds to wait for each ldd or patchelf invocation before killing it
+    #[options(default = "60")]
+    pub timeout: u64,
+
+    /// how many more times to run ldd or patchelf after a transient failure, eg: a busy file or a failed fork. each retry waits twice as long as the previous one
+    #[options(default = "2")]
+    pub retries: u32,
+
+    /// read the needed libs, loader, rpath and soname of each file straight from its ELF headers, instead of running patchelf and ldd for them. patchelf is still used to change files, and ldd to find where the libs are
+    pub native_elf: bool,
+
+    /// path to the patchelf binary to use instead of looking it up on the PATH
+    pub patchelf_path: Option<PathBuf>,
+
+    /// path to the ldd binary to use instead of looking it up on the PATH
+    pub ldd_path: Option<PathBuf>,
+
+    /// read the libs and the loader that ldd resolves from under this directory instead of from /
+    pub sysroot: Option<PathBuf>,
+
+    #[options(free)]
+    pub exepath: Vec<PathBuf>
+}
+
+#[derive(Debug, Options)]
+pub struct CheckCli {
+    /// prints the help
+    pub help: bool,
+
+    /// number of seconds to wait for each patchelf invocation before killing it
+    #[options(default = "60")]
+    pub timeout: u64,
+
+    /// how many more times to run ldd or patchelf after a transient failure, eg: a busy file or a failed fork. each retry waits twice as long as the previous one
+    #[options(default = "2")]
+    pub retries: u32,
+
+    /// read the needed libs, loader and rpath of each file straight from its ELF headers, instead of running patchelf for them
+    pub native_elf: bool,
+
+    /// path to the patchelf binary to use instead of looking it up on the PATH
+    pub patchelf_path: Option<PathBuf>,
+
+    /// the rpath every patched file must have. by default, the rpaths that packing sets are accepted
+    pub expect_rpath: Option<String>,
+
+    #[options(free)]
+    pub dir: Vec<PathBuf>
+}
+
+#[derive(Debug, Options)]
+pub struct DoctorCli {
+    /// prints the help
+    pub help: bool,
+
+    /// number of seconds to wait for each ldd or patchelf invocation before killing it
+    #[options(default = "60")]
+    pub timeout: u64,
+
+    /// path to the patchelf binary to check instead of the one on the PATH
+    pub patchelf_path: Option<PathBuf>,
+
+    /// path to the ldd binary to check instead of the one on the PATH
+    pub ldd_path: Option<PathBuf>,
+
+    /// also fail if this tool is missing, eg: upx when packing with --upx. can be repeated
+    pub require: Vec<String>,
+}
+
+/// also deserialized from --options-json, with the same field names
+#[derive(Debug, Clone, Options, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackCli {
+    /// prints the help
+    #[serde(skip)]
+    pub help: bool,
+
+    /// read the options as a JSON object from this file, or from stdin with -. its fields are the flags with - changed to _, and the executables go in exepath. any fields it leaves out keep the value from the command line, eg: {"exepath": ["/usr/bin/ls"], "output": "ls_out", "make_wrapper": true}
+    #[serde(skip)]
+    pub options_json: Option<PathBuf>,
+
     /// print detailed logging info to stderr
     pub verbose: bool,
 
-    /// name of folder to be created that will contain the archive of all of the shared libs
+    /// make the output, and any tarball of it, the same bytes every time for the same inputs: libs are copied in sorted order, every file's mtime is set to SOURCE_DATE_EPOCH (or 0 if unset), and tarballs are sorted with no owner
+    pub reproducible: bool,
+
+    /// once done, print a single json object to stdout with the number of libraries bundled, the bytes copied, how many libs were deduplicated, excluded, failed, found in the base or left for the target, and the elapsed_ms
+    pub summary_json: bool,
+
+    /// print how long the traversal, the copying and the patching took to stderr. this is also printed with --verbose
+    pub timings: bool,
+
+    /// write one json object per line to stderr for each step, for tools that show progress: resolve_started, resolved for each lib, copied and patched for each file, and done. each has the name it is about and a timestamp_ms. this replaces the progress bar
+    pub progress_json: bool,
+
+    /// do not show the progress of copying files
+    pub quiet: bool,
+
+    /// name of folder to be created that will contain the archive of all of the shared libs. pass - to build it in a temporary folder and write it to stdout as a tarball instead
     #[options(short = "o")]
     pub output: Option<PathBuf>,
 
-    /// if the output archive already exists by default we exit with an error and a message. if you pass the --force flag, we will override it
+    /// append a stamp to the output folder's name, to keep several builds side by side. build-id appends the GNU build-id of the first executable, and time the current UTC time, eg: out-20240131T235959Z
+    pub stamp: Option<String>,
+
+    /// append this string to the output folder's name instead of a --stamp, eg: a version like 1.2.0
+    pub stamp_value: Option<String>,
+
+    /// if the output archive already exists by default we exit with an error and a message. if you pass the --force flag, we will replace it, once the new one is complete
     #[options(short = "f")]
     pub force: bool,
 
     /// whatever the executable is, wrap it in a shell script that calls the executable with the correct LD_LIBRARY_PATH for you
     pub make_wrapper: bool,
 
+    /// also bundle the parts of glibc that it loads at runtime by path, and that ldd therefore never lists: the NSS modules (libnss_*) for looking up hosts and users, and the gconv modules for iconv. the wrapper points GCONV_PATH at the bundled gconv modules
+    pub glibc_extras: bool,
+
+    /// once packed, run the bundled loader with --verify on each executable, which makes sure it can load it without running the executable. skipped with a warning if the loader does not support --verify
+    pub verify_runnable: bool,
+
+    /// once packed, print each executable's needed libs like ldd does, but with the file in the output that each one now resolves to, to make sure they all point inside the output
+    pub show_resolved: bool,
+
+    /// the directory the output will be installed to. the executables' interpreter is set to the loader there instead of to ./loader, and the rpath to $ORIGIN, so that they can be run directly from anywhere once installed. without this, run them through the wrapper or from inside the output
+    pub absolute_interp: Option<PathBuf>,
+
+    /// make a wrapper like --make-wrapper, but leave the executables' interpreter as it is. the wrapper runs them with the bundled loader, and run directly they still use the system one
+    pub wrapper_only_loader: bool,
+
+    /// after resolving dependencies, write a CycloneDX JSON software bill of materials of every bundled file to this path
+    pub sbom: Option<PathBuf>,
+
+    /// record the source paths in the sbom relative to this directory when they are under it, so that sboms made from different build directories can be diffed
+    pub manifest_base: Option<PathBuf>,
+
+    /// after copying, write a JSON array to this path with the name, source path and status of every lib found during traversal. the status is one of copied, mapped, excluded, failed, in-base or on-target
+    pub report: Option<PathBuf>,
+
+    /// after copying, write a SHA256SUMS file into the output that can be verified with sha256sum -c
+    pub checksums: bool,
+
+    /// number of seconds to wait for each ldd or patchelf invocation before killing it
+    #[options(default = "60")]
+    pub timeout: u64,
+
+    /// how many more times to run ldd or patchelf after a transient failure, eg: a busy file or a failed fork. each retry waits twice as long as the previous one
+    #[options(default = "2")]
+    pub retries: u32,
+
+    /// read the needed libs, loader, rpath and soname of each file straight from its ELF headers, instead of running patchelf and ldd for them. patchelf is still used to change files, and ldd to find where the libs are
+    pub native_elf: bool,
+
+    /// path to the patchelf binary to use instead of looking it up on the PATH
+    pub patchelf_path: Option<PathBuf>,
+
+    /// path to the ldd binary to use instead of looking it up on the PATH
+    pub ldd_path: Option<PathBuf>,
+
+    /// after copying, check that every needed lib of every file in the output is also in the output
+    pub check_closure: bool,
+
+    /// only bundle the libs and the loader, and do not copy the executable itself. this disables --make-wrapper
+    pub libs_only: bool,
+
+    /// if packing fails, leave the partial output directory in place for debugging instead of removing it
+    pub keep_on_error: bool,
+
+    /// add to an existing output instead of requiring --force. files already in it are never overwritten, and ones whose contents differ are reported
+    pub merge: bool,
+
+    /// copy the loader under the name of the real file it resolves to, and symlink the name the executables reference to it if the two differ
+    pub loader_symlink: bool,
+
+    /// reuse an existing output, and only copy and patch files whose source changed since the last --incremental run
+    pub incremental: bool,
+
+    /// leave the executable under its own name instead of moving it to .execname-original, and write the wrapper next to it as execname plus --wrapper-suffix
+    pub no_wrapper_rename: bool,
+
+    /// the suffix of the wrapper's name with --no-wrapper-rename
+    #[options(default = ".sh")]
+    pub wrapper_suffix: String,
+
+    /// KEY=VALUE environment variable to export in the wrapper script before launching the executable. can be repeated
+    pub wrapper_env: Vec<String>,
+
+    /// once given, the wrapper starts the executable with an empty environment except for the variables named with this, if they are set, and the ones from --wrapper-env. can be repeated
+    pub wrapper_env_passthrough: Vec<String>,
+
+    /// make the wrapper script export LD_BIND_NOW=1 so all symbols are resolved at startup
+    pub bind_now: bool,
+
+    /// NAME=PATH to bundle the file at PATH as the lib NAME, instead of the file that ldd finds for it. can be repeated
+    pub map: Vec<String>,
+
+    /// path to a lib to bundle even though no executable needs it, eg: a plugin that is dlopened at runtime. its own needed libs are bundled too. can be repeated
+    pub extra_lib: Vec<PathBuf>,
+
+    /// read additional executable paths from this file, one per line. blank lines and lines starting with # are ignored. pass - to read from stdin
+    pub from_file: Option<PathBuf>,
+
+    /// instead of replacing the rpath of each copied file, put $ORIGIN in front of the rpath it already has
+    pub append_rpath: bool,
+
+    /// after copying, list the bundled files that no executable reaches through its needed libs. they are not removed
+    pub report_unused: bool,
+
+    /// read the libs and the loader that ldd resolves from under this directory instead of from /. for packing the output of a build sysroot or container stage
+    pub sysroot: Option<PathBuf>,
+
+    /// name to give the executable inside the output, and in its wrapper, instead of the name of its source file. only works with a single executable
+    pub name: Option<String>,
+
+    /// an executable path that is a symlink is always analyzed and copied from the file it resolves to, and by default is bundled under that file's name, eg: python -> python3.11 is bundled as python3.11. with this, it is bundled under the symlink's name instead
+    pub keep_symlink_name: bool,
+
+    /// set patchelf's --no-default-lib on the executables, so the loader never looks in the system lib dirs. the bundle is then strictly self contained, and a lib missing from it fails loudly instead of silently loading from the host
+    pub no_default_lib: bool,
+
+    /// in-place patches each file after copying it into the output. temp-then-move patches it in a scratch directory next to the output and then moves it in, so a patchelf crash never leaves a partially patched file in the output
+    #[options(default = "in-place")]
+    pub patch_strategy: PatchStrategy,
+
+    /// when a file fails to copy or patch, skip it and carry on with the rest. the files that failed are listed at the end, and the exit code is nonzero if there were any
+    pub best_effort: bool,
+
+    /// put each lib under the output at the path it was copied from, eg: out/usr/lib/libc.so.6, instead of next to the executables, and point the rpaths at those directories
+    pub mirror_paths: bool,
+
+    /// print what would be copied where, and check that every source file can be read and the output can be written, but dont copy anything
+    pub dry_run: bool,
+
+    /// after patching each file, read its interpreter and rpath back with patchelf and fail if they are not what was set. this costs two more patchelf runs per file
+    pub verify_patch: bool,
+
+    /// once the output has been checked, compress every copied executable and lib with upx. the loader is never compressed
+    pub upx: bool,
+
+    /// dir writes the output as a folder. squashfs builds the folder in a temporary directory, and writes it as a compressed squashfs image to the output path instead, which defaults to sharedpacker_out.squashfs. needs mksquashfs
+    #[options(default = "dir")]
+    pub format: OutputFormat,
+
+    /// after packing, also write the output folder as a gzipped tarball to this path
+    pub targz: Option<PathBuf>,
+
+    /// after packing, write a single shell script to this path that extracts the output to a temporary directory and runs the wrapper of the first executable in it. implies --make-wrapper
+    pub self_extract: Option<PathBuf>,
+
+    /// write a freedesktop .desktop file into the output that launches the wrapper of the executable, eg: for an AppDir. implies --make-wrapper
+    pub desktop_entry: bool,
+
+    /// the Name of the --desktop-entry. defaults to the executable's name
+    pub desktop_name: Option<String>,
+
+    /// the Icon of the --desktop-entry
+    pub desktop_icon: Option<String>,
+
+    /// write an install.sh into the output that copies the bundle to <prefix>/lib and links the wrappers from <prefix>/bin. implies --make-wrapper
+    pub gen_installer: bool,
+
+    /// after packing, copy the finished output to this directory and move it into place, replacing any previous install there. concurrent installs to the same directory wait for each other
+    pub install_to: Option<PathBuf>,
+
+    /// octal mode to set on the bundled executables and their wrappers, eg: 0755. by default they keep the mode of their source files
+    pub exec_mode: Option<String>,
+
+    /// fail, and remove the partial output, once the copied files add up to more than this size. eg: 500M or 2G.
+    /// with --upx, this is their size before compressing them
+    pub max_size: Option<String>,
+
+    /// glob of lib names to leave out of the output, eg: 'libGL*'. can be repeated
+    pub exclude: Vec<String>,
+
+    /// only bundle the libs that the executables need directly, and not the libs that those need in turn, eg: when the target already has the deeper ones
+    pub no_recurse: bool,
+
+    /// when two bundled libs have the same SONAME, only bundle the first, and patch the files that need the other one to need it instead. what was pruned is printed
+    pub prune_duplicate_sonames: bool,
+
+    /// glob of lib names that are allowed in the output. once any are given, a lib (or the loader) that is going to be bundled and matches none of them fails the run. can be repeated
+    pub allow: Vec<String>,
+
+    /// dont bundle any lib whose SHA-256 is already listed under its name in this manifest, to make a bundle of only what a base image lacks. the manifest is an --sbom or a SHA256SUMS file, eg: from sha256sum run over the base's lib dirs
+    pub base_manifest: Option<PathBuf>,
+
+    /// how many files to copy into the output at once. copying is bound by the disk, so a fast disk can take more than --patch-jobs
+    #[options(default = "1")]
+    pub copy_jobs: usize,
+
+    /// how many patchelf runs to have going at once. each one is a process, so this is bound by the number of cores
+    #[options(default = "1")]
+    pub patch_jobs: usize,
+
+    /// add this to the end of every rpath, so that the libs left out by --base-manifest are found where the base has them, eg: /usr/lib/x86_64-linux-gnu
+    pub base_rpath: Option<String>,
+
+    /// dont bundle any lib that this file lists, to bundle only what the target lacks. the file has one lib name per line, optionally followed by the version the target has, eg: libssl.so.3 3.0.2, and then a lib is only left out when its file has that version. the rpath keeps $ORIGIN, and the listed libs are found on the target's system path. what was left out is printed
+    pub target_inventory: Option<PathBuf>,
+
+    /// dont bundle any lib whose source path is under this directory. can be repeated
+    pub exclude_path: Vec<PathBuf>,
+
+    /// pack every job in this JSON file, an array of {"exe", "output", "make_wrapper", "exclude"} objects. the other flags apply to every job. a failed job does not stop the others
+    pub jobs_file: Option<PathBuf>,
+
+    /// with a directory as an executable path, also pack the executables in its subdirectories, not just the ones directly in it
+    pub recursive: bool,
+
     #[options(free)]
     pub exepath: Vec<PathBuf>
 }
 
-#[derive(Debug, Clone)]
-pub struct SharedLib {
-    pub name: String,
-    pub path: PathBuf,
+/// how long --install-to waits for another install to the same place to finish
+const INSTALL_LOCK_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// the output that the Ctrl-C handler removes, if the current pack created it
+static INTERRUPT_CLEANUP: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+fn set_interrupt_cleanup(output: Option<PathBuf>) {
+    if let Ok(mut cleanup) = INTERRUPT_CLEANUP.lock() {
+        *cleanup = output;
+    }
 }
 
-#[derive(Debug)]
-pub struct DependencyNode {
-    pub name: String,
-    pub path: PathBuf,
-    pub dependencies: Vec<String>,
+fn exit_with(e: Error) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(e.exit_code());
 }
 
-pub fn parse_ldd_output(
-    path: &Path,
-    only_loader: bool,
-) -> Result<Vec<SharedLib>, String> {
-    let strthing: &str = path.to_str().map_or_else(|| Err("Failed to get path as string"), |o| Ok(o))?;
-    let exec_args = [
-        "ldd", strthing,
-    ];
-    let output = exechelper::execute(&exec_args)
-        .map_err(|e| e.to_string())?;
-    if output.status != 0 {
-        return Err(output.stderr);
-    }
-
-    // eprintln!("GOT OUTPUT: \n{}", output.stdout);
-    let mut outvec = vec![];
-
-    // rules for parsing ldd output:
-    // - must start with at least one empty whitespace char
-    //   because its possible ldd might display some header info that
-    //   we dont want to parse
-    // - must contain an arrow '=>' otherwise it is something thats statically linked?
-    for line in output.stdout.lines() {
-        if !line.starts_with(' ') && !line.starts_with('\t') {
-            continue;
-        }
-        let no_whitespace = line.trim_start().trim_end();
-        if !no_whitespace.contains(" => ") {
-            // also want to check if we have the loader here
-            // because sometime the loader can be present without
-            // the => mapping
-            // which should be fine because usually the loader has
-            // its full path as the first entry anyway
-            if only_loader && no_whitespace.starts_with('/') {
-                let loader_path = match no_whitespace.split(' ').next() {
-                    Some(p) => p,
-                    None => continue,
-                };
-                let loader_name = loader_path.rsplit('/').next().unwrap_or(loader_path);
-                return Ok(vec![SharedLib {
-                    name: loader_name.into(),
-                    path: loader_path.into(),
-                }]);
-            }
+/// the names that are taken as a subcommand when they are the first argument
+const COMMAND_NAMES: [&str; 4] = ["deps", "pack", "check", "doctor"];
 
-            continue;
-        }
+/// like gumdrop's parse_args_default_or_exit, but inserts the pack
+/// subcommand when the arguments dont start with one
+fn parse_args_or_exit() -> Cli {
+    let args: Vec<String> = std::env::args().collect();
+    let mut cli_args: Vec<String> = args[1..].to_vec();
+    let starts_with_command = cli_args.first().is_some_and(|a| {
+        COMMAND_NAMES.contains(&a.as_str()) || ["-h", "--help", "-V", "--version"].contains(&a.as_str())
+    });
+    if !starts_with_command && !cli_args.is_empty() {
+        cli_args.insert(0, "pack".into());
+    }
 
-        let mut split = no_whitespace.split(" => ");
-        let libname = split.next().map_or_else(|| Err("Failed to parse ldd output"), |l| Ok(l))?;
-        let pathpart = split.next().map_or_else(|| Err("Failed to parse ldd output"), |l| Ok(l))?;
-        if pathpart.contains("not found") {
-            return Err(format!("Dependency on {} is not found", libname));
+    let cli = Cli::parse_args_default(&cli_args).unwrap_or_else(|e| {
+        // a usage error, which is 1 like the rest of them. 2 is for a missing tool
+        eprintln!("{}: {}", args[0], e);
+        std::process::exit(1);
+    });
+    if cli.help_requested() || (cli.command.is_none() && !cli.version) {
+        let (command_str, usage) = match &cli.command {
+            Some(command) => (format!(" {}", command.command_name().unwrap_or_default()), command.self_usage()),
+            None => (String::new(), cli.self_usage()),
+        };
+        eprintln!("Usage: {}{} [OPTIONS]", args[0], command_str);
+        eprintln!();
+        eprintln!("{}", usage);
+        if cli.command.is_none() {
+            eprintln!();
+            eprintln!("Available commands:");
+            eprintln!("{}", Cli::command_list().unwrap_or_default());
         }
+        std::process::exit(if cli.help_requested() { 0 } else { 1 });
+    }
+    cli
+}
 
-        // if we are not considering the loader, then ignore when path starts with /
-        // which i assume only happens for the loader?
-        if !only_loader && libname.starts_with('/') {
-            continue;
-        }
+fn main() {
+    let cli = parse_args_or_exit();
+    if cli.version {
+        println!("sharedpacker {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+    match cli.command {
+        Some(Command::Deps(deps_cli)) => run_deps(deps_cli),
+        Some(Command::Pack(pack_cli)) => run_pack(pack_cli),
+        Some(Command::Check(check_cli)) => run_check(check_cli),
+        Some(Command::Doctor(doctor_cli)) => run_doctor(doctor_cli),
+        None => {}
+    }
+}
 
-        // if we are only interested in finding the loader
-        // and we see that the libname starts with the /
-        // then parse out the loader name
-        let is_loader = only_loader && libname.starts_with('/');
-        let libname = if is_loader {
-            // if this is the loader it will usually start with /
-            // so we want to remove its base bath and just have the file name
-            libname.rsplit('/').next().unwrap_or(libname)
-        } else { libname };
+/// makes the tool config from the cli flags, after making sure any
+/// tool paths that were given point at executables
+fn make_tool_config(
+    ldd_path: &Option<PathBuf>,
+    patchelf_path: &Option<PathBuf>,
+    timeout: u64,
+    native_elf: bool,
+    retries: u32,
+) -> Result<ToolConfig, Error> {
+    for tool_path in patchelf_path.iter().chain(ldd_path.iter()) {
+        validate_tool_path(tool_path)?;
+    }
+    Ok(ToolConfig {
+        ldd: ldd_path.as_ref().map_or("ldd".into(), |p| p.to_string_lossy().to_string()),
+        patchelf: patchelf_path.as_ref().map_or("patchelf".into(), |p| p.to_string_lossy().to_string()),
+        timeout: Duration::from_secs(timeout),
+        native_elf,
+        retries,
+    })
+}
 
-        let pathpart = match pathpart.find(' ') {
-            None => pathpart,
-            Some(index) => {
-                &pathpart[0..index]
+/// prints every lib that each executable needs as "name => path", then its loader
+fn run_deps(cli: DepsCli) {
+    if cli.exepath.is_empty() {
+        eprintln!("Must provide at least one path to an executable\n{}", cli.self_usage());
+        std::process::exit(1);
+    }
+    let tools = match make_tool_config(&cli.ldd_path, &cli.patchelf_path, cli.timeout, cli.native_elf, cli.retries) {
+        Ok(t) => t,
+        Err(e) => exit_with(e),
+    };
+    let opts = ResolveOptions { sysroot: cli.sysroot.clone(), verbose: cli.verbose };
+    for (i, execpath) in cli.exepath.iter().enumerate() {
+        let (dependencies, loader) = match resolve_dependencies(&tools, execpath, &opts) {
+            Ok(r) => r,
+            Err(e) => exit_with(e.context(&format!("Failed to resolve {:?}", execpath))),
+        };
+        if cli.exepath.len() > 1 {
+            if i > 0 {
+                println!();
             }
+            println!("{}:", execpath.display());
+        }
+        // the executable's own node is the last one
+        for dep in &dependencies[..dependencies.len().saturating_sub(1)] {
+            println!("{} => {}", dep.name, dep.path.display());
+        }
+        println!("{} => {}", loader.name, loader.path.display());
+    }
+}
+
+/// checks each bundle directory, printing every problem found in it
+fn run_check(cli: CheckCli) {
+    if cli.dir.is_empty() {
+        eprintln!("Must provide at least one bundle directory to check\n{}", cli.self_usage());
+        std::process::exit(1);
+    }
+    let tools = match make_tool_config(&None, &cli.patchelf_path, cli.timeout, cli.native_elf, cli.retries) {
+        Ok(t) => t,
+        Err(e) => exit_with(e),
+    };
+    let mut found_problems = false;
+    for dir in &cli.dir {
+        if !dir.is_dir() {
+            exit_with(Error::Io(format!("{:?} is not a directory", dir)));
+        }
+        let problems = match check_bundle(&tools, dir, cli.expect_rpath.as_deref()) {
+            Ok(p) => p,
+            Err(e) => exit_with(e.context(&format!("Failed to check {:?}", dir))),
         };
+        if problems.is_empty() {
+            println!("{}: ok", dir.display());
+        } else {
+            found_problems = true;
+            println!("{}: {} problems", dir.display(), problems.len());
+            for problem in &problems {
+                println!("  {}", problem);
+            }
+        }
+    }
+    if found_problems {
+        std::process::exit(1);
+    }
+}
 
-        // if we are only interested in the loader
-        // and this one is the loader, then instead of outputting to the vec
-        // just return here because we found it
-        if is_loader {
-            return Ok(vec![SharedLib {
-                name: libname.into(),
-                path: pathpart.into(),
-            }]);
+/// prints the version of each tool, and what is wrong with it if anything.
+/// exits nonzero if a required tool is missing or unusable
+fn run_doctor(cli: DoctorCli) {
+    let tools = match make_tool_config(&cli.ldd_path, &cli.patchelf_path, cli.timeout, false, 0) {
+        Ok(t) => t,
+        Err(e) => exit_with(e),
+    };
+    // patchelf is tried out on a copy of ourselves, which is dynamically linked like what gets packed
+    let probe = match std::env::current_exe() {
+        Ok(p) => p,
+        Err(e) => exit_with(Error::Io(format!("Failed to find the sharedpacker executable\n{}", e))),
+    };
+    let mut failed = false;
+    for status in check_tools(&tools, &probe, &cli.require) {
+        let optional_use = OPTIONAL_TOOLS.iter().find(|(name, _)| *name == status.name).map(|(_, what)| *what);
+        match (&status.problem, status.required) {
+            (None, _) => println!("{}: {}", status.name, status.version.as_deref().unwrap_or_default()),
+            (Some(problem), true) => {
+                failed = true;
+                println!("{}: problem", status.name);
+                for line in problem.lines() {
+                    println!("  {}", line);
+                }
+            }
+            (Some(_), false) => println!("{}: not found (optional, {})", status.name, optional_use.unwrap_or("not required")),
         }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
 
-        outvec.push(SharedLib {
-            name: libname.into(),
-            path: pathpart.into(),
-        });
+/// the pack subcommand
+fn run_pack(mut cli: PackCli) {
+    if let Some(path) = &cli.options_json {
+        let options = read_options_json(path).and_then(|json| overlay_json_options(&cli, &json));
+        match options {
+            Ok(options) => cli = options,
+            Err(e) => exit_with(e.context("Invalid --options-json")),
+        }
+    }
+    if let Some(from_file) = &cli.from_file {
+        match read_exepaths_from_file(from_file) {
+            Ok(paths) => cli.exepath.extend(paths),
+            Err(e) => exit_with(e),
+        }
+    }
+    // a directory stands for the executables in it, which all share one lib archive
+    match expand_exe_dirs(&cli.exepath, cli.recursive, cli.verbose) {
+        Ok(paths) => cli.exepath = paths,
+        Err(e) => exit_with(e),
+    }
+    if cli.jobs_file.is_some() {
+        let per_output = [
+            ("executable paths", !cli.exepath.is_empty()),
+            ("--output", cli.output.is_some()),
+            ("--name", cli.name.is_some()),
+            ("--sbom", cli.sbom.is_some()),
+            ("--report", cli.report.is_some()),
+            ("--targz", cli.targz.is_some()),
+            ("--self-extract", cli.self_extract.is_some()),
+            ("--install-to", cli.install_to.is_some()),
+        ];
+        for (what, given) in per_output {
+            if given {
+                eprintln!("Cannot use {} with --jobs-file, because each job has its own executable and output", what);
+                std::process::exit(1);
+            }
+        }
+    } else if cli.exepath.is_empty() {
+        let usage = cli.self_usage();
+        eprintln!("Must provide at least one path to an executable\n{}", usage);
+        std::process::exit(1);
+    }
+    if cli.verbose {
+        eprintln!("{:#?}\n", cli);
+    }
+    if cli.progress_json {
+        events::enable();
+    }
+    if let Some(name) = &cli.name {
+        if cli.exepath.len() > 1 {
+            eprintln!("--name can only be used when packing a single executable");
+            std::process::exit(1);
+        }
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            eprintln!("--name must be a plain file name, but got {:?}", name);
+            std::process::exit(1);
+        }
+    }
+    if cli.self_extract.is_some() && cli.libs_only {
+        eprintln!("--self-extract runs the executable, so it cannot be used with --libs-only");
+        std::process::exit(1);
     }
+    if cli.output.as_deref() == Some(Path::new("-")) && cli.summary_json {
+        eprintln!("--output - writes the tarball to stdout, so it cannot be used with --summary-json");
+        std::process::exit(1);
+    }
+    if cli.output.as_deref() == Some(Path::new("-")) && (cli.dry_run || cli.incremental || cli.merge) {
+        eprintln!("--output - builds a new bundle every time, so it cannot be used with --dry-run, --incremental or --merge");
+        std::process::exit(1);
+    }
+    if cli.format == OutputFormat::Squashfs && (cli.output.as_deref() == Some(Path::new("-")) || cli.incremental || cli.merge) {
+        eprintln!("--format squashfs builds a new image every time, so it cannot be used with --output -, --incremental or --merge");
+        std::process::exit(1);
+    }
+    if cli.target_inventory.is_some() && cli.no_default_lib {
+        eprintln!("--target-inventory leaves libs for the target's system path to provide, so it cannot be used with --no-default-lib");
+        std::process::exit(1);
+    }
+    if cli.mirror_paths && cli.check_closure {
+        eprintln!("--check-closure expects every lib next to the executables, so it cannot be used with --mirror-paths");
+        std::process::exit(1);
+    }
+    if cli.stamp.as_deref().is_some_and(|stamp| stamp != "build-id" && stamp != "time") {
+        eprintln!("--stamp must be build-id or time. to stamp with any other string, use --stamp-value");
+        std::process::exit(1);
+    }
+    if cli.stamp.is_some() && cli.stamp_value.is_some() {
+        eprintln!("--stamp and --stamp-value each pick the stamp, so only one of them can be given");
+        std::process::exit(1);
+    }
+    if cli.stamp_value.as_deref().is_some_and(|value| value.is_empty() || value.contains('/')) {
+        eprintln!("--stamp-value must be a non-empty string without a /");
+        std::process::exit(1);
+    }
+    if (cli.stamp.is_some() || cli.stamp_value.is_some()) && cli.output.as_deref() == Some(Path::new("-")) {
+        eprintln!("--output - streams the bundle rather than writing a folder, so it cannot be stamped");
+        std::process::exit(1);
+    }
+    if cli.no_recurse && cli.check_closure {
+        eprintln!("--no-recurse leaves out the libs of the libs on purpose, so it cannot be used with --check-closure");
+        std::process::exit(1);
+    }
+    if cli.absolute_interp.as_deref().is_some_and(|dir| !dir.is_absolute()) {
+        eprintln!("--absolute-interp must be an absolute path");
+        std::process::exit(1);
+    }
+    if cli.absolute_interp.is_some() && cli.wrapper_only_loader {
+        eprintln!("--wrapper-only-loader leaves the interpreter alone, so it cannot be used with --absolute-interp");
+        std::process::exit(1);
+    }
+    if cli.wrapper_only_loader && cli.libs_only {
+        eprintln!("--wrapper-only-loader wraps the executables, so it cannot be used with --libs-only");
+        std::process::exit(1);
+    }
+    if cli.gen_installer && cli.libs_only {
+        eprintln!("--gen-installer links the executables, so it cannot be used with --libs-only");
+        std::process::exit(1);
+    }
+    if cli.desktop_entry && cli.libs_only {
+        eprintln!("--desktop-entry launches the executable, so it cannot be used with --libs-only");
+        std::process::exit(1);
+    }
+    if !cli.desktop_entry && (cli.desktop_name.is_some() || cli.desktop_icon.is_some()) {
+        eprintln!("--desktop-name and --desktop-icon only apply with --desktop-entry");
+        std::process::exit(1);
+    }
+    if cli.no_wrapper_rename && (cli.wrapper_suffix.is_empty() || cli.wrapper_suffix.contains('/')) {
+        eprintln!("--wrapper-suffix must be a non empty suffix without a /, but got {:?}", cli.wrapper_suffix);
+        std::process::exit(1);
+    }
+    if cli.upx {
+        if let Err(e) = check_upx_installed() {
+            exit_with(e);
+        }
+    }
+    if cli.format == OutputFormat::Squashfs {
+        if let Err(e) = check_mksquashfs_installed() {
+            exit_with(e);
+        }
+    }
+    let max_size = match cli.max_size.as_deref().map(parse_size) {
+        Some(Ok(size)) => Some(size),
+        Some(Err(e)) => exit_with(e.context("Invalid --max-size")),
+        None => None,
+    };
+    if let Some(Err(e)) = cli.exec_mode.as_deref().map(parse_mode) {
+        exit_with(e.context("Invalid --exec-mode"));
+    }
+    let mut wrapper_env = vec![];
+    for pair in &cli.wrapper_env {
+        match parse_env_pair(pair) {
+            Ok(kv) => wrapper_env.push(kv),
+            Err(e) => exit_with(e.context("Invalid --wrapper-env")),
+        }
+    }
+    if cli.bind_now {
+        wrapper_env.push(("LD_BIND_NOW".into(), "1".into()));
+    }
+    if let Some(name) = cli.wrapper_env_passthrough.iter().find(|name| !is_valid_env_name(name)) {
+        eprintln!("Invalid --wrapper-env-passthrough: {:?} is not a valid environment variable name", name);
+        std::process::exit(1);
+    }
+    let tools = match make_tool_config(&cli.ldd_path, &cli.patchelf_path, cli.timeout, cli.native_elf, cli.retries) {
+        Ok(t) => t,
+        Err(e) => exit_with(e),
+    };
 
-    Ok(outvec)
-}
+    let handler = ctrlc::set_handler(|| {
+        if let Ok(cleanup) = INTERRUPT_CLEANUP.lock() {
+            if let Some(output) = &*cleanup {
+                eprintln!("Interrupted, removing partial output {:?}", output);
+                cleanup_if_err(output);
+            }
+        }
+        // exiting skips Drop, so the temporary folders are removed here
+        remove_pending_dirs();
+        std::process::exit(130);
+    });
+    if let Err(e) = handler {
+        eprintln!("Failed to install Ctrl-C handler: {}", e);
+    }
 
-pub fn get_lib_path_list(
-    path: &Path,
-) -> Result<Vec<SharedLib>, String> {
-    parse_ldd_output(path, false)
+    match &cli.jobs_file {
+        Some(jobs_file) => run_jobs(&cli, jobs_file, &tools, &wrapper_env, max_size),
+        None => if let Err(e) = pack(&cli, &tools, &wrapper_env, max_size) {
+            exit_with(e);
+        },
+    }
 }
 
-pub fn get_loader(
-    path: &Path,
-) -> Result<SharedLib, String> {
-    let loader = parse_ldd_output(path, true)?;
-    match loader.get(0) {
-        Some(lib) => Ok(lib.clone()),
-        None => Err(format!("Failed to get loader from {:?}", path)),
+/// prints what packing would copy where, and checks that every source file can be
+/// read and that the output can be written to, without copying anything
+fn dry_run(
+    output_name: &Path,
+    dependencies: &[DependencyNode],
+    loader: &SharedLib,
+    execnames: &[String],
+) -> Result<(), Error> {
+    let mut problems = vec![];
+    println!("Would pack into {:?}:", output_name);
+    for dep in dependencies {
+        let what = if execnames.contains(&dep.name) { "executable" } else { "lib" };
+        println!("  {} <= {:?} ({})", dep.name, dep.path, what);
+        if let Err(e) = check_readable(&dep.path) {
+            problems.push(e);
+        }
+    }
+    println!("  {} <= {:?} (loader)", loader.name, loader.path);
+    if let Err(e) = check_readable(&loader.path) {
+        problems.push(e);
+    }
+    if let Err(e) = check_output_writable(output_name) {
+        problems.push(e);
     }
-}
 
-/// use patchelf to find a list of needed libs from an executable
-pub fn get_needed_libs(
-    path: &Path
-) -> Result<Vec<String>, String> {
-    let strthing: &str = path.to_str().map_or_else(|| Err("Failed to get path as string"), |o| Ok(o))?;
-    let exec_args = [
-        "patchelf", "--print-needed", strthing,
-    ];
-    let output = exechelper::execute(&exec_args)
-        .map_err(|e| e.to_string())?;
-    if output.status != 0 {
-        return Err(output.stderr);
+    if problems.is_empty() {
+        println!("Every source file can be read, and the output can be written");
+        return Ok(());
+    }
+    eprintln!("Packing would fail because of these access problems:");
+    for problem in &problems {
+        eprintln!("  {}", problem.to_string().replace('\n', "\n    "));
     }
+    Err(Error::Io(format!("Dry run found {} access problems", problems.len())))
+}
 
-    let mut outvec = vec![];
-    for line in output.stdout.lines() {
-        let trimmed: String = line.trim_start().trim_end().into();
-        // TODO: should ignore loader or not?
-        if trimmed.starts_with("ld-linux") {
-            continue;
+/// packs every job of the jobs file with the cli's flags plus the job's own
+/// settings, and then prints which ones failed. exits with the exit code of the
+/// first failed job
+fn run_jobs(
+    cli: &PackCli,
+    jobs_file: &Path,
+    tools: &ToolConfig,
+    wrapper_env: &[(String, String)],
+    max_size: Option<u64>,
+) {
+    let jobs = match read_jobs_file(jobs_file) {
+        Ok(j) => j,
+        Err(e) => exit_with(e),
+    };
+    let mut results = vec![];
+    for job in &jobs {
+        let mut job_cli = cli.clone();
+        job_cli.exepath = vec![job.exe.clone()];
+        job_cli.output = Some(job.output.clone());
+        job_cli.make_wrapper = job.make_wrapper.unwrap_or(cli.make_wrapper);
+        job_cli.exclude.extend(job.exclude.iter().cloned());
+        let result = pack(&job_cli, tools, wrapper_env, max_size);
+        if let Err(e) = &result {
+            eprintln!("Job {:?} failed: {}", job.exe, e);
         }
-        outvec.push(trimmed);
+        results.push(result);
     }
 
-    Ok(outvec)
+    eprintln!("\nPacked {} of {} jobs:", results.iter().filter(|r| r.is_ok()).count(), jobs.len());
+    for (job, result) in jobs.iter().zip(&results) {
+        let status = if result.is_ok() { "ok" } else { "FAILED" };
+        eprintln!("  {:6} {:?} -> {:?}", status, job.exe, job.output);
+    }
+    if let Some(Err(e)) = results.iter().find(|r| r.is_err()) {
+        std::process::exit(e.exit_code());
+    }
 }
 
-pub fn traverse_dependencies(
-    known_lib_location_map: &mut HashMap<String, PathBuf>,
-    use_libs: &mut Vec<String>,
-    dependency_nodes: &mut Vec<DependencyNode>,
-    needed_path: &Path,
-    needed_name: &str,
-    verbose: bool,
-    log_prefix: &str,
-) -> Result<(), String> {
-    // eprintln!("Looking for needed: {:?}", needed_path);
-    // first we iterate over its dependencies, and add the known paths
-    // to our map:
-    let shared_libs = match get_lib_path_list(needed_path) {
-        Ok(l) => l,
-        Err(e) => return Err(e),
+/// the whole pipeline for the cli's executables: traverse, copy and patch,
+/// and then whatever extra outputs were asked for
+fn pack(
+    cli: &PackCli,
+    tools: &ToolConfig,
+    wrapper_env: &[(String, String)],
+    max_size: Option<u64>,
+) -> Result<(), Error> {
+    let traversal_started = Instant::now();
+    events::emit("resolve_started", Some(&cli.exepath[0].to_string_lossy()));
+    // every executable is bundled with the loader of the first one
+    let loader = resolve_loader(tools, &cli.exepath[0], cli.sysroot.as_deref())?;
+    check_single_loader(tools, &cli.exepath[1..], &loader, cli.sysroot.as_deref())?;
+    events::emit("resolved", Some(&loader.name));
+    let loader_names = loader_names(&loader);
+    let mut lib_location_map = HashMap::new();
+    for pair in &cli.map {
+        let (name, path) = pair.split_once('=')
+            .ok_or_else(|| Error::Other(format!("Expected --map NAME=PATH but got {:?}", pair)))?;
+        map_lib(&mut lib_location_map, name, Path::new(path))?;
+    }
+    let mut used_libs = vec![];
+    let mut dependencies = vec![];
+    let mut execnames: Vec<String> = vec![];
+    let traverse_opts = TraverseOptions {
+        loader_names: &loader_names,
+        sysroot: cli.sysroot.as_deref(),
+        max_depth: if cli.no_recurse { Some(1) } else { None },
+        verbose: cli.verbose,
+        log_prefix: "",
     };
-    for lib in shared_libs {
-        // eprintln!("PATH: {:?}", lib);
-        if !known_lib_location_map.contains_key(&lib.name) {
-            known_lib_location_map.insert(lib.name, lib.path);
+
+    // every executable is traversed against the same maps so that
+    // they all end up sharing one archive of libs
+    for execpath in &cli.exepath {
+        if cli.verbose {
+            eprintln!("{}", color::path(&format!("{:?}", execpath)));
         }
+
+        // a symlink is followed to the real file, which is what ldd and patchelf would read anyway
+        let real_path = std::fs::canonicalize(execpath)
+            .map_err(|e| Error::Io(format!("Failed to resolve executable path {:?}\n{}", execpath, e)))?;
+        let name_path = if cli.keep_symlink_name { execpath } else { &real_path };
+        let execname = match &cli.name {
+            Some(name) => name.clone(),
+            None => name_path.file_name()
+                .ok_or_else(|| Error::Other(format!("Failed to get exec path file name from {:?}", name_path)))?
+                .to_string_lossy().to_string(),
+        };
+        if execnames.contains(&execname) {
+            return Err(Error::Other(format!("Cannot pack two executables with the same name: {}", execname)));
+        }
+
+        traverse_dependencies(
+            tools, &mut lib_location_map, &mut used_libs, &mut dependencies, &real_path, &execname, &traverse_opts
+        ).map_err(|e| e.context("Failed to traverse dependencies"))?;
+        execnames.push(execname);
     }
 
-    let mut dependency_node = DependencyNode {
-        name: needed_name.into(),
-        path: needed_path.into(),
-        dependencies: vec![]
+    // with --glibc-extras, the NSS modules next to libc are bundled as extra libs,
+    // and the gconv modules are copied once the output exists
+    let libc_dir = match dependencies.iter().find(|dep| dep.name == LIBC_NAME) {
+        Some(libc) if cli.glibc_extras => libc.path.parent().map(|dir| dir.to_path_buf()),
+        _ => None,
     };
-    // next we get all of the actually needed dependencies of this file
-    // and for each dependency, we recurse and do this process again, each
-    // time appending the use_libs list of libs that we will ultimately use
-    let needed_shared_libs = get_needed_libs(needed_path)?;
-    for lib in needed_shared_libs {
-        dependency_node.dependencies.push(lib.clone());
-
-        // find this libs path from our map
-        let lib_path = match known_lib_location_map.get(&lib) {
-            Some(p) => p.clone(),
-            None => {
-                return Err(format!("Found needed library that we don't know a location of: {}", lib));
-            }
-        };
+    if cli.glibc_extras && libc_dir.is_none() {
+        eprintln!("Warning: not adding any glibc extras, because nothing needs {}", LIBC_NAME);
+    }
+    let nss_libs = match &libc_dir {
+        Some(dir) => find_nss_libs(dir)?,
+        None => vec![],
+    };
+    let gconv_dir = libc_dir.as_deref().and_then(find_gconv_dir);
+    if let (Some(dir), None) = (&libc_dir, &gconv_dir) {
+        eprintln!("Warning: found no gconv modules next to {:?}, so none are bundled", dir.join(LIBC_NAME));
+    }
 
-        // dont recurse for a lib name that weve already found
-        if !use_libs.contains(&lib) {
-            let next_log_prefix = format!("{}  ", log_prefix);
-            if verbose {
-                eprintln!("{}{} => {:?}", next_log_prefix, lib, lib_path);
+    // extra libs are more roots of the same traversal, that are copied and patched like any other lib
+    let mut extra_lib_names = vec![];
+    for lib_path in cli.extra_lib.iter().chain(&nss_libs) {
+        let lib_name = lib_path.file_name()
+            .ok_or_else(|| Error::Other(format!("Failed to get file name of extra lib {:?}", lib_path)))?
+            .to_string_lossy().to_string();
+        if !lib_path.is_file() {
+            return Err(Error::MissingDependency(format!("Extra lib {:?} does not exist", lib_path)));
+        }
+        if !used_libs.contains(&lib_name) {
+            if cli.verbose {
+                eprintln!("{}", color::path(&format!("{:?}", lib_path)));
             }
+            used_libs.push(lib_name.clone());
+            traverse_dependencies(
+                tools, &mut lib_location_map, &mut used_libs, &mut dependencies, lib_path, &lib_name, &traverse_opts
+            ).map_err(|e| e.context(&format!("Failed to traverse dependencies of extra lib {:?}", lib_path)))?;
+        }
+        extra_lib_names.push(lib_name);
+    }
+    let traversed_count = dependencies.len();
+    // the copy leaves out what the filter does not keep. the checks before
+    // copying only look at the libs that are going to be bundled
+    let mut filters = vec![LibFilter::excluding(cli.exclude.clone(), cli.exclude_path.clone())];
+    if let Some(path) = &cli.target_inventory {
+        filters.push(LibFilter::missing_from(
+            read_target_inventory(path).map_err(|e| e.context("Failed to read --target-inventory"))?));
+    }
+    let lib_filter = LibFilter::all(filters);
+    let will_bundle = |dep: &DependencyNode| execnames.contains(&dep.name) || lib_filter.keeps(dep);
+    let mut dependencies = dedupe_by_path(dependencies);
+    if cli.prune_duplicate_sonames {
+        // a lib that is left out cannot stand in for another one
+        let (mut bundled, left_out): (Vec<_>, Vec<_>) = dependencies.into_iter().partition(|dep| will_bundle(dep));
+        let pruned = prune_duplicate_sonames(tools, &mut bundled, &execnames)?;
+        for (name, kept_name) in &pruned {
+            eprintln!("Pruned {}, which has the same SONAME as {}", name, kept_name);
+        }
+        bundled.extend(left_out);
+        dependencies = bundled;
+    }
+    if cli.reproducible {
+        dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    let bundled: Vec<DependencyNode> = dependencies.iter().filter(|dep| will_bundle(dep)).cloned().collect();
+    let traversal_time = traversal_started.elapsed();
+    let bundled_names = bundled.iter()
+        .filter(|dep| !execnames.contains(&dep.name))
+        .map(|dep| dep.name.as_str())
+        .chain(std::iter::once(loader.name.as_str()));
+    let disallowed = find_disallowed(bundled_names, &cli.allow);
+    if !disallowed.is_empty() {
+        return Err(Error::Other(format!(
+            "These libs match none of the --allow patterns: {}. allow or --exclude each of them",
+            disallowed.join(", "))));
+    }
 
-            // prevent duplicates (yes its inefficient, but
-            use_libs.push(lib.clone());
+    if cli.verbose {
+        eprintln!("\nNeed these libs: {:#?}\n", used_libs);
+        eprintln!("{:#?}", bundled);
+    }
 
-            traverse_dependencies(
-                known_lib_location_map, use_libs, dependency_nodes,
-                &lib_path, &lib, verbose, &next_log_prefix)?;
+    // with --output -, the bundle is built in a temporary folder, that is
+    // removed once it has been streamed to stdout
+    let streaming = cli.output.as_deref() == Some(Path::new("-"));
+    let mut _stream_dir = None;
+    let output_name = if streaming {
+        let stream_dir = std::env::temp_dir().join(format!("sharedpacker-stream-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&stream_dir);
+        std::fs::create_dir_all(&stream_dir)
+            .map_err(|e| Error::Io(format!("Failed to create {:?}\n{}", stream_dir, e)))?;
+        let output_name = stream_dir.join("sharedpacker_out");
+        _stream_dir = Some(RemoveOnDrop::new(stream_dir));
+        output_name
+    } else {
+        let default_name = match cli.format {
+            OutputFormat::Squashfs => "sharedpacker_out.squashfs",
+            OutputFormat::Dir => "sharedpacker_out",
+        };
+        let output_name = normalize_output_path(&cli.output.clone().unwrap_or(default_name.into()))?;
+        let stamp = match (cli.stamp.as_deref(), &cli.stamp_value) {
+            (_, Some(value)) => Some(value.clone()),
+            (Some("build-id"), _) => Some(read_build_id(&cli.exepath[0])?.ok_or_else(|| Error::Other(format!(
+                "{:?} has no GNU build-id to stamp the output with. link it with -Wl,--build-id, or use --stamp time",
+                cli.exepath[0])))?),
+            (Some(_), _) => Some(time_stamp()?),
+            (None, None) => None,
+        };
+        match stamp {
+            Some(stamp) => {
+                let stamped = stamp_output_path(&output_name, &stamp);
+                if !cli.quiet {
+                    eprintln!("Writing the output to {:?}", stamped);
+                }
+                stamped
+            }
+            None => output_name,
+        }
+    };
+    // with --format squashfs, the output path is the image, and the folder is
+    // built in a temporary folder like with --output -
+    let mut image_path = None;
+    let mut output_name = if cli.format == OutputFormat::Squashfs {
+        if output_name.symlink_metadata().is_ok() && !cli.force {
+            return Err(Error::Other(format!("Output {:?} already exists. use --force if you want to override", output_name)));
+        }
+        if output_name.is_dir() {
+            return Err(Error::Other(format!("Output {:?} already exists and is a directory, not a squashfs image", output_name)));
         }
+        image_path = Some(output_name);
+        let build_dir = std::env::temp_dir().join(format!("sharedpacker-squashfs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&build_dir);
+        std::fs::create_dir_all(&build_dir)
+            .map_err(|e| Error::Io(format!("Failed to create {:?}\n{}", build_dir, e)))?;
+        _stream_dir = Some(RemoveOnDrop::new(build_dir.clone()));
+        build_dir.join("sharedpacker_out")
+    } else { output_name };
+    // symlink_metadata so that a dangling symlink also counts as existing
+    let output_existed = output_name.symlink_metadata().is_ok();
+    if output_existed && !cli.force && !cli.incremental && !cli.merge {
+        return Err(Error::Other(format!("Output {:?} already exists. use --force if you want to override", output_name)));
+    }
+    if output_existed && !output_name.is_dir() {
+        return Err(Error::Other(format!("Output {:?} already exists and is not a directory", output_name)));
     }
 
-    dependency_nodes.push(dependency_node);
-    Ok(())
-}
+    let sources = bundled.iter().map(|d| d.path.as_path()).chain(std::iter::once(loader.path.as_path()));
+    check_output_is_not_a_source_dir(&output_name, sources)?;
 
-pub fn cleanup_if_err(archive_path: &PathBuf) {
-    let _ = std::fs::remove_dir_all(archive_path);
-}
+    check_architectures(&cli.exepath[0], &bundled, &loader)
+        .map_err(|e| e.context("Architecture mismatch"))?;
 
-pub fn patch_shared_lib(
-    libname: &str,
-    object_path: &PathBuf
-) -> Result<(), String> {
-    let new_name = format!("./{}", libname);
-    let obj_path_str = object_path.to_string_lossy().to_string();
-    let exec_args = [
-        "patchelf", "--replace-needed", libname, &new_name[..], &obj_path_str
-    ];
-    let output = exechelper::execute(&exec_args).map_err(|e| e.to_string())?;
-    if output.status != 0 {
-        return Err(output.stderr);
+    if cli.dry_run {
+        return dry_run(&output_name, &bundled, &loader, &execnames);
     }
 
-    Ok(())
-}
+    if let Some(sbom_path) = &cli.sbom {
+        let manifest_base = match &cli.manifest_base {
+            Some(base) => Some(std::fs::canonicalize(base)
+                .map_err(|e| Error::Io(format!("Failed to resolve --manifest-base {:?}\n{}", base, e)))?),
+            None => None,
+        };
+        make_sbom(&bundled, &loader, &execnames, manifest_base.as_deref())
+            .and_then(|sbom| std::fs::write(sbom_path, sbom).map_err(|e| Error::Io(e.to_string())))
+            .map_err(|e| e.context(&format!("Failed to write sbom to {:?}", sbom_path)))?;
+    }
 
-pub fn patch_loader(
-    loader: &str,
-    object_path: &PathBuf,
-) -> Result<(), String> {
-    let new_name = format!("./{}", loader);
-    let obj_path_str = object_path.to_string_lossy().to_string();
-    let exec_args = [
-        "patchelf", "--set-interpreter", &new_name, "--set-rpath", ".", &obj_path_str
-    ];
-    let output = exechelper::execute(&exec_args).map_err(|e| e.to_string())?;
-    if output.status != 0 {
-        // patchelf can give error:
-        // cannot find section '.interp'. The input file is most likely statically linked
-        // when the linker is statically linked. ignore this error
-        if output.stderr.contains("statically linked") {
-            return Ok(())
-        }
-        return Err(format!("Failed to patch loader for {:?}\n{}", object_path, output.stderr));
+    // with --force, an existing output is only replaced once the new one is complete,
+    // so a failure or an interrupt before then leaves the previous one as it was
+    let replaced_output = if output_existed && cli.force && !cli.incremental && !cli.merge {
+        let staging = sibling_path(&output_name, &format!("building-{}", std::process::id()))?;
+        let _ = std::fs::remove_dir_all(&staging);
+        Some(std::mem::replace(&mut output_name, staging))
+    } else { None };
+    let _staging_dir = match &replaced_output {
+        Some(_) if !cli.keep_on_error => Some(RemoveOnDrop::new(output_name.clone())),
+        _ => None,
+    };
+    // only remove what this run created
+    let remove_on_error = !cli.keep_on_error && (!output_existed || replaced_output.is_some());
+    if remove_on_error {
+        set_interrupt_cleanup(Some(output_name.clone()));
     }
 
-    Ok(())
-}
+    let base_libs = match &cli.base_manifest {
+        Some(path) => read_base_manifest(path).map_err(|e| e.context("Failed to read --base-manifest"))?,
+        None => HashMap::new(),
+    };
 
-pub fn make_shell_script_wrapper(
-    execname: &str,
-    loadername: &str,
-) -> String {
-    // https://stackoverflow.com/a/4774063
-    let part_one: String = "#!/usr/bin/env bash\n\nSCRIPTPATH=\"$( cd -- \"$(dirname \"$0\")\" >/dev/null 2>&1 ; pwd -P )\"".into();
-    let part_two = format!("\"$SCRIPTPATH/{}\" --library-path \"$SCRIPTPATH\" \"$SCRIPTPATH/{}\" \"$@\"", loadername, execname);
-    let out = format!("{}\n{}", part_one, part_two);
-    out
-}
+    // now iterate over the flat list of dependencies and copy all of them
+    // to the output folder
+    if cli.libs_only && cli.make_wrapper {
+        eprintln!("Not making a wrapper because --libs-only does not copy the executable");
+    }
+    let copy_opts = CopyOptions {
+        make_wrapper: (cli.make_wrapper || cli.wrapper_only_loader || cli.self_extract.is_some() || cli.desktop_entry || cli.gen_installer) && !cli.libs_only,
+        incremental: cli.incremental,
+        merge: cli.merge,
+        libs_only: cli.libs_only,
+        loader_symlink: cli.loader_symlink,
+        wrapper_suffix: if cli.no_wrapper_rename { Some(cli.wrapper_suffix.clone()) } else { None },
+        interpreter_dir: cli.absolute_interp.clone(),
+        keep_interpreter: cli.wrapper_only_loader,
+        wrapper: WrapperOptions {
+            env: wrapper_env.to_vec(),
+            passthrough: cli.wrapper_env_passthrough.clone(),
+            bundle_paths: match gconv_dir {
+                Some(_) => vec![("GCONV_PATH".to_string(), GCONV_DIR_NAME.to_string())],
+                None => vec![],
+            },
+            ..Default::default()
+        },
+        append_rpath: cli.append_rpath,
+        no_default_lib: cli.no_default_lib,
+        verify_patch: cli.verify_patch,
+        patch_strategy: cli.patch_strategy,
+        best_effort: cli.best_effort,
+        mirror_paths: cli.mirror_paths,
+        max_size,
+        verbose: cli.verbose,
+        progress: !cli.quiet && !cli.progress_json,
+        base_libs,
+        base_rpath: cli.base_rpath.clone(),
+        filter: Some(lib_filter),
+        copy_jobs: cli.copy_jobs,
+        patch_jobs: cli.patch_jobs,
+        exec_mode: cli.exec_mode.as_deref().map(parse_mode).transpose()?,
+    };
+    let copied = copy_dependencies_to_output_folder(
+        tools, &output_name, &dependencies, &loader, &execnames, &copy_opts,
+    );
+    set_interrupt_cleanup(None);
+    let report = match copied {
+        Ok(r) => r,
+        Err(e) => {
+            if remove_on_error {
+                cleanup_if_err(&output_name);
+            } else if replaced_output.is_some() {
+                eprintln!("Kept the partial output in {:?}, the existing output was left as it was", output_name);
+            }
+            return Err(e.context("Failed to copy dependencies to output folder"));
+        }
+    };
+    // the filter left out both the excluded libs and the ones the target already has
+    let (excluded, on_target): (Vec<&DependencyNode>, Vec<&DependencyNode>) = dependencies.iter()
+        .filter(|dep| report.filtered.contains(&dep.name))
+        .partition(|dep| excludes(dep, &cli.exclude, &cli.exclude_path));
+    if let Some(gconv_dir) = &gconv_dir {
+        if let Err(e) = copy_gconv_modules(gconv_dir, &output_name) {
+            if remove_on_error {
+                cleanup_if_err(&output_name);
+            }
+            return Err(e.context("Failed to copy the gconv modules"));
+        }
+    }
+    if let Some(final_output) = replaced_output {
+        move_into_place(&output_name, &final_output)
+            .map_err(|e| e.context(&format!("Failed to replace the existing output {:?}", final_output)))?;
+        output_name = final_output;
+    }
+    if cli.verbose {
+        eprintln!("Applied {} patchelf operations to {} files, with one patchelf run per file to write them",
+            report.patch_operations, report.patched_files);
+    }
+    if cli.verbose || cli.timings {
+        eprintln!("Timings: traversal {:.2?}, copying {:.2?}, patching {:.2?}",
+            traversal_time, report.copy_time, report.patch_time);
+        if cli.copy_jobs > 1 || cli.patch_jobs > 1 {
+            eprintln!("  copying and patching are added up over every file, so they can be more than the wall clock time with --copy-jobs or --patch-jobs");
+        }
+    }
+    if cli.verbose && !report.in_base.is_empty() {
+        eprintln!("Left out these libs, which the base manifest already has: {}", report.in_base.join(", "));
+    }
+    if !on_target.is_empty() {
+        let names: Vec<&str> = on_target.iter().map(|dep| dep.name.as_str()).collect();
+        eprintln!("Left out these libs, which the target already has: {}", names.join(", "));
+    }
+    if !report.collisions.is_empty() {
+        eprintln!("Kept the existing copies of these files, which differ from the ones being added:");
+        for collision in &report.collisions {
+            eprintln!("  {}", collision);
+        }
+    }
 
-pub fn copy_dependencies_to_output_folder(
-    archive_path: &PathBuf,
-    dependencies: &Vec<DependencyNode>,
-    loader: &SharedLib,
-    execname: &str,
-    make_wrapper: bool,
-) -> Result<(), String> {
-    std::fs::create_dir_all(&archive_path).map_err(|e| e.to_string())?;
+    if let Some(report_path) = &cli.report {
+        let mapped: Vec<&str> = cli.map.iter().filter_map(|pair| pair.split_once('=')).map(|(name, _)| name).collect();
+        let mut records = vec![];
+        for dep in dependencies.iter().filter(|d| !execnames.contains(&d.name)) {
+            let status = if report.failures.contains(&dep.name) {
+                LibStatus::Failed
+            } else if report.in_base.contains(&dep.name) {
+                LibStatus::InBase
+            } else if excluded.iter().any(|e| e.name == dep.name) {
+                LibStatus::Excluded
+            } else if report.filtered.contains(&dep.name) {
+                LibStatus::OnTarget
+            } else if mapped.contains(&dep.name.as_str()) {
+                LibStatus::Mapped
+            } else { LibStatus::Copied };
+            records.push(LibRecord { name: dep.name.clone(), path: Some(dep.path.clone()), status });
+        }
+        records.push(LibRecord { name: loader.name.clone(), path: Some(loader.path.clone()), status: LibStatus::Copied });
+        std::fs::write(report_path, make_report_json(&records))
+            .map_err(|e| Error::Io(format!("Failed to write report to {:?}\n{}", report_path, e)))?;
+    }
 
-    for dep in dependencies {
-        let dep_path = &dep.path;
-        let filename = dep_path.file_name()
-            .map_or_else(|| Err(format!("Failed to find file name for {:?}", dep_path)), |o| Ok(o))?;
-        let mut output_path = archive_path.clone();
-        output_path.push(filename);
-
-        std::fs::copy(dep_path, &output_path)
-            .map_err(|e| format!("Failed to copy {:?} to {:?}\n{}", dep_path, output_path, e))?;
-
-        // now change the loader to point to the specific one we copied
-        patch_loader(&loader.name, &output_path)?;
-    }
-
-    // finally, copy the loader itself
-    let mut new_loader_path = archive_path.clone();
-    new_loader_path.push(loader.name.clone());
-    std::fs::copy(&loader.path, &new_loader_path)
-        .map_err(|e| format!("Failed to copy loader {:?} to {:?}\n{}", loader.path, new_loader_path, e))?;
-
-    // also, if user wants to make a wrapper, we replace the archive_path/execname
-    // with archive_path/.execname-original and make archive_path/execname a shell script
-    // that launches archive_path/.execname-original with the correct LD_LIBRARY_PATH
-    let mut old_exec = archive_path.clone();
-    old_exec.push(execname);
-    let mut new_exec = archive_path.clone();
-    let newname = format!(".{}-original", execname);
-    new_exec.push(&newname);
-    if make_wrapper {
-        std::fs::rename(&old_exec, &new_exec)
-            .map_err(|e| format!("Failed to rename {:?} to {:?}\n{}", old_exec, new_exec, e))?;
-        // now make the shell script
-        let wrapper = make_shell_script_wrapper(&newname, &loader.name);
-        std::fs::write(&old_exec, wrapper)
-            .map_err(|e| e.to_string())?;
-        // also make it executable:
-        let old_exec_path = old_exec.to_string_lossy();
-        let exec_args = ["chmod", "+x", &old_exec_path];
-        match exechelper::execute(&exec_args) {
-            Ok(out) => if out.status != 0 { return Err(out.stderr) },
-            Err(e) => { return Err(e.to_string()) },
+    let print_summary = || {
+        let failed_libs = report.failures.iter().filter(|f| !execnames.contains(f)).count();
+        let summary = Summary {
+            // the loader is a lib too
+            libraries: dependencies.iter().filter(|d| !execnames.contains(&d.name)).count() + 1
+                - failed_libs - report.in_base.len() - report.filtered.len(),
+            bytes: report.copied_bytes,
+            deduplicated: traversed_count - dependencies.len(),
+            excluded: excluded.len(),
+            failed: failed_libs,
+            in_base: report.in_base.len(),
+            on_target: on_target.len(),
+            elapsed_ms: traversal_started.elapsed().as_millis(),
+        };
+        println!("{}", make_summary_json(&summary));
+    };
+
+    if !report.failures.is_empty() {
+        // the run fails, but the summary still says what made it and what did not
+        if cli.summary_json {
+            print_summary();
+        }
+        eprintln!("These files failed to copy or patch, and are missing from the output:");
+        for failure in &report.failures {
+            let source = dependencies.iter().find(|d| d.name == *failure).map(|d| d.path.clone()).unwrap_or_default();
+            eprintln!("  {} (from {:?})", failure, source);
         }
+        return Err(Error::Other(format!("Failed to bundle {} of {} files", report.failures.len(), dependencies.len())));
     }
 
-    Ok(())
-}
+    if cli.report_unused {
+        let roots: Vec<String> = execnames.iter().chain(extra_lib_names.iter()).cloned().collect();
+        let unused = find_unreachable(&dependencies, &roots);
+        if unused.is_empty() {
+            eprintln!("Every bundled file is needed by an executable");
+        } else {
+            eprintln!("These bundled files are not needed by any executable, consider excluding them:");
+            for name in &unused {
+                eprintln!("  {}", name);
+            }
+        }
+    }
 
-fn main() {
-    let cli = <Cli as Options>::parse_args_default_or_exit();
-    let execpath = match cli.exepath.get(0) {
-        Some(o) => o,
-        None => {            
-            let usage = cli.self_usage();
-            eprintln!("Must provide at least one path to an executable\n{}", usage);
-            std::process::exit(1);
+    if cli.desktop_entry {
+        let entry = make_desktop_entry(
+            cli.desktop_name.as_deref().unwrap_or(&execnames[0]),
+            &copy_opts.wrapper_name(&execnames[0]),
+            cli.desktop_icon.as_deref());
+        let entry_path = output_name.join(format!("{}.desktop", execnames[0]));
+        std::fs::write(&entry_path, entry)
+            .map_err(|e| Error::Io(format!("Failed to write desktop entry {:?}\n{}", entry_path, e)))?;
+    }
+
+    if cli.gen_installer {
+        let bundle_name = output_name.file_name()
+            .ok_or_else(|| Error::Other(format!("Failed to find folder name of {:?}", output_name)))?
+            .to_string_lossy().to_string();
+        let wrapper_names: Vec<String> = execnames.iter().map(|e| copy_opts.wrapper_name(e)).collect();
+        let script_path = output_name.join(INSTALL_SCRIPT_NAME);
+        std::fs::write(&script_path, make_install_script(&bundle_name, &wrapper_names))
+            .map_err(|e| Error::Io(format!("Failed to write installer {:?}\n{}", script_path, e)))?;
+        let script_path_str = script_path.to_string_lossy();
+        let out = execute_checked(&["chmod", "+x", &script_path_str])?;
+        if out.status != 0 {
+            return Err(Error::Io(out.stderr));
         }
-    };
-    if cli.verbose {
-        eprintln!("{:#?}\n", cli);
     }
-    let mut lib_location_map = HashMap::new();
-    let mut used_libs = vec![];
-    let mut dependencies = vec![];
 
-    if cli.verbose {
-        eprintln!("{:?}", execpath);
+    if cli.verify_runnable && !cli.libs_only {
+        let exec_files: Vec<String> = execnames.iter()
+            .filter(|name| !report.failures.contains(name))
+            .map(|name| copy_opts.wrapped_exec_name(name)).collect();
+        if !verify_runnable(tools, &output_name, &loader.name, &exec_files)? {
+            eprintln!("Warning: not verifying that the executables are runnable, because the loader {} does not support --verify", loader.name);
+        }
     }
 
-    // we also copy the original exec path given to us
-    let execname = execpath.file_name().unwrap_or_else(|| {
-        eprintln!("Failed to get exec path file name from {:?}", execpath);
-        std::process::exit(1);
-    }).to_string_lossy().to_string();
+    if cli.show_resolved && !cli.libs_only {
+        for execname in execnames.iter().filter(|name| !report.failures.contains(name)) {
+            let exec_file = copy_opts.wrapped_exec_name(execname);
+            eprintln!("{}:", exec_file);
+            for (name, path) in resolve_in_bundle(tools, &output_name, &exec_file, &loader_names)? {
+                match path {
+                    Some(path) if path.starts_with(&output_name) => eprintln!("\t{} => {}", name, path.display()),
+                    Some(path) => eprintln!("\t{} => {} (outside the output)", name, path.display()),
+                    None => eprintln!("\t{} => not found", name),
+                }
+            }
+        }
+    }
 
-    if let Err(e) = traverse_dependencies(
-        &mut lib_location_map, &mut used_libs, &mut dependencies,
-        execpath, &execname, cli.verbose, ""
-    ) {
-        eprintln!("Failed to traverse dependencies: {}", e);
-        std::process::exit(1);
+    if cli.check_closure {
+        // the libs that were excluded by path, or that the target has, are left out on purpose too
+        let closure_exclude: Vec<String> = cli.exclude.iter().cloned()
+            .chain(report.in_base.iter().cloned())
+            .chain(report.filtered.iter().cloned()).collect();
+        check_closure(tools, &output_name, &loader_names, &closure_exclude)?;
     }
 
-    if cli.verbose {
-        eprintln!("\nNeed these libs: {:#?}\n", used_libs);
-        eprintln!("{:#?}", dependencies);
+    // after every check above, which all read the dynamic sections that upx packs away
+    if cli.upx {
+        compress_files(&output_name, &report.copied_files).map_err(|e| e.context("Failed to compress the output"))?;
     }
 
-    let output_name = cli.output.unwrap_or("sharedpacker_out".into());
-    if output_name.is_dir() && output_name.exists() && !cli.force {
-        eprintln!("Output directory {:?} already exists. use --force if you want to override", output_name);
-        std::process::exit(1);
+    if cli.checksums {
+        write_checksums_file(&output_name).map_err(|e| e.context("Failed to write checksums"))?;
     }
 
-    let loader = match get_loader(&execpath) {
-        Ok(l) => l,
-        Err(e) => {
-            eprintln!("{}", e);
-            std::process::exit(1);
-        }
-    };
+    // only now that nothing else is written into the output
+    let mtime = if cli.reproducible { Some(reproducible_epoch()?) } else { None };
+    if let Some(mtime) = mtime {
+        set_mtimes(&output_name, mtime).map_err(|e| e.context("Failed to make the output reproducible"))?;
+    }
 
-    // now iterate over the flat list of dependencies and copy all of them
-    // to the output folder
-    if let Err(e) = copy_dependencies_to_output_folder(
-        &output_name, &dependencies, &loader, &execname, cli.make_wrapper,
-    ) {
-        eprintln!("Failed to copy dependencies to output folder: {}", e);
-        std::process::exit(1);
+    if let Some(targz_path) = &cli.targz {
+        make_targz(&output_name, targz_path, mtime).map_err(|e| e.context("Failed to write tarball"))?;
+    }
+
+    if let Some(script_path) = &cli.self_extract {
+        make_self_extracting(&output_name, &copy_opts.wrapper_name(&execnames[0]), script_path, mtime)
+            .map_err(|e| e.context("Failed to write self extracting archive"))?;
+    }
+
+    if let Some(install_path) = &cli.install_to {
+        let install_path = normalize_output_path(install_path)?;
+        install_archive(&output_name, &install_path, INSTALL_LOCK_TIMEOUT)
+            .map_err(|e| e.context(&format!("Failed to install to {:?}", install_path)))?;
     }
+
+    if streaming {
+        write_tar_to_stdout(&output_name, mtime)?;
+    }
+    if let Some(image_path) = &image_path {
+        make_squashfs(&output_name, image_path, mtime).map_err(|e| e.context("Failed to write the squashfs image"))?;
+    }
+    if cli.summary_json {
+        print_summary();
+    }
+    events::emit("done", None);
+    Ok(())
 }
@@ -0,0 +1,46 @@
+use std::{io::Read, path::Path};
+use serde::{Serialize, de::DeserializeOwned};
+use crate::Error;
+
+/// reads a JSON options object from the file at path, or from stdin when path is -
+pub fn read_options_json(path: &Path) -> Result<String, Error> {
+    if path == Path::new("-") {
+        let mut contents = String::new();
+        std::io::stdin().read_to_string(&mut contents)
+            .map_err(|e| Error::Io(format!("Failed to read options from stdin\n{}", e)))?;
+        return Ok(contents);
+    }
+    std::fs::read_to_string(path)
+        .map_err(|e| Error::Io(format!("Failed to read options from {:?}\n{}", path, e)))
+}
+
+/// the options with each field of the JSON object set to its value. the fields that the object
+/// leaves out keep their value from defaults, eg: the cli's defaults, so the object can have every
+/// field or only a few. a field that the options dont have is an error that lists the ones they do
+pub fn overlay_json_options<T: Serialize + DeserializeOwned>(defaults: &T, json: &str) -> Result<T, Error> {
+    let given: serde_json::Value = serde_json::from_str(json)
+        .map_err(|e| Error::Other(format!("Failed to parse options JSON: {}", e)))?;
+    let given = match given {
+        serde_json::Value::Object(given) => given,
+        _ => return Err(Error::Other("The options JSON must be an object of option names to values".into())),
+    };
+    let mut options = match serde_json::to_value(defaults) {
+        Ok(serde_json::Value::Object(options)) => options,
+        Ok(_) => return Err(Error::Other("The options do not serialize to a JSON object".into())),
+        Err(e) => return Err(Error::Other(format!("Failed to serialize the default options: {}", e))),
+    };
+    let mut unknown: Vec<&str> = given.keys().map(|k| k.as_str()).filter(|k| !options.contains_key(*k)).collect();
+    if !unknown.is_empty() {
+        unknown.sort();
+        let mut known: Vec<&String> = options.keys().collect();
+        known.sort();
+        return Err(Error::Other(format!(
+            "Unknown options in the options JSON: {}\nthe known options are: {}",
+            unknown.join(", "), known.iter().map(|k| k.as_str()).collect::<Vec<_>>().join(", "))));
+    }
+    for (key, value) in given {
+        options.insert(key, value);
+    }
+    serde_json::from_value(serde_json::Value::Object(options))
+        .map_err(|e| Error::Other(format!("Invalid options JSON: {}", e)))
+}
@@ -0,0 +1,261 @@
+use std::path::Path;
+use crate::Error;
+use crate::tools::{ToolConfig, execute_tool};
+use crate::elf::read_interpreter;
+use crate::elftool::ElfTool;
+
+/// the --page-size values to retry patchelf with when it runs out of room, from the
+/// page size most targets use up to the largest one an arch uses, eg: aarch64 with 64k pages
+pub const PAGE_SIZE_RETRIES: [&str; 3] = ["4096", "16384", "65536"];
+
+/// whether patchelf failed because it could not grow the program headers of a file in place,
+/// eg: a large binary whose headers are packed tightly against its first sections
+pub fn is_no_room_error(stderr: &str) -> bool {
+    stderr.contains("room for the program headers")
+}
+
+/// runs patchelf with args followed by the file. when it fails with is_no_room_error, it is
+/// run again with each of PAGE_SIZE_RETRIES, since the page size it aligns the new segment
+/// to decides whether there is room for it. if every one of them fails too, the last
+/// output is returned with a note of what was tried, so the caller reports it as usual
+fn run_patchelf(
+    tools: &ToolConfig,
+    args: &[&str],
+    object_path: &Path,
+) -> Result<exechelper::CommandOutput, Error> {
+    let obj_path_str = object_path.to_string_lossy().to_string();
+    let mut exec_args: Vec<&str> = vec![&tools.patchelf];
+    exec_args.extend(args);
+    exec_args.push(&obj_path_str);
+    let mut output = execute_tool(tools, &exec_args)?;
+    if output.status == 0 || !is_no_room_error(&output.stderr) {
+        return Ok(output);
+    }
+    for page_size in PAGE_SIZE_RETRIES {
+        let mut retry_args: Vec<&str> = vec![&tools.patchelf, "--page-size", page_size];
+        retry_args.extend(&exec_args[1..]);
+        output = execute_tool(tools, &retry_args)?;
+        if output.status == 0 || !is_no_room_error(&output.stderr) {
+            return Ok(output);
+        }
+    }
+    output.stderr = format!(
+        "{}patchelf could not find room for the program headers, even with --page-size {}. \
+        a newer patchelf may manage, otherwise leave this file out of the bundle\n",
+        output.stderr, PAGE_SIZE_RETRIES.join(", "));
+    Ok(output)
+}
+
+/// reads one field of an ELF file with a patchelf --print-* flag. None
+/// if patchelf says the file is statically linked, and so has no such field
+pub(crate) fn print_field(
+    tools: &ToolConfig,
+    flag: &str,
+    object_path: &Path,
+) -> Result<Option<String>, Error> {
+    let obj_path_str = object_path.to_string_lossy().to_string();
+    let exec_args = [&tools.patchelf, flag, &obj_path_str];
+    let output = execute_tool(tools, &exec_args)?;
+    if output.status != 0 {
+        if output.stderr.contains("statically linked") {
+            return Ok(None);
+        }
+        return Err(Error::Patch(format!("Failed to run patchelf {} on {:?}\n{}", flag, object_path, output.stderr)));
+    }
+    // some tools print the interpreter with its terminating nul
+    Ok(Some(output.stdout.trim().trim_end_matches('\0').into()))
+}
+
+/// reads the interpreter of an ELF file. None if it is statically linked
+pub fn get_interpreter(
+    tools: &ToolConfig,
+    object_path: &Path,
+) -> Result<Option<String>, Error> {
+    print_field(tools, "--print-interpreter", object_path)
+}
+
+/// reads the patched file back and makes sure its interpreter and rpath are the ones
+/// that ops set, because patchelf can exit successfully on a malformed ELF without changing it
+pub fn verify_patch_ops(
+    tools: &dyn ElfTool,
+    ops: &PatchOps,
+    object_path: &Path,
+) -> Result<(), Error> {
+    if let Some(expected) = &ops.set_interpreter {
+        // from the file itself, since tools.interpreter falls back to the loader ldd reports
+        let actual = read_interpreter(object_path)
+            .map_err(|e| Error::Patch(format!("Failed to read back the interpreter of {:?}\n{}", object_path, e)))?;
+        match actual {
+            Some(actual) if actual == *expected => {}
+            Some(actual) => return Err(Error::Patch(format!(
+                "patchelf reported success, but the interpreter of {:?} is {:?} instead of {:?}",
+                object_path, actual, expected))),
+            None => return Err(Error::Patch(format!(
+                "patchelf reported success, but {:?} has no interpreter instead of {:?}", object_path, expected))),
+        }
+    }
+    if let Some(expected) = &ops.set_rpath {
+        // a statically linked file was left alone by apply_patch_ops
+        let actual = match tools.rpath(object_path)? {
+            Some(a) => a,
+            None => return Ok(()),
+        };
+        if actual != *expected {
+            return Err(Error::Patch(format!(
+                "patchelf reported success, but the rpath of {:?} is {:?} instead of {:?}",
+                object_path, actual, expected)));
+        }
+    }
+    Ok(())
+}
+
+/// puts first in front of the entries of an existing rpath, dropping
+/// any existing entry equal to it so that re-packing doesnt repeat it
+pub fn combine_rpath(first: &str, existing: &str) -> String {
+    let mut entries = vec![first];
+    for entry in existing.split(':') {
+        if !entry.is_empty() && !entries.contains(&entry) {
+            entries.push(entry);
+        }
+    }
+    entries.join(":")
+}
+
+/// the entries of an rpath that are relative to $ORIGIN, but point at a directory other than
+/// the file's own, eg: $ORIGIN/../lib. those directories are not there in a flat archive
+pub fn origin_subdir_entries(rpath: &str) -> Vec<String> {
+    rpath.split(':')
+        .filter(|entry| {
+            let rest = entry.strip_prefix("${ORIGIN}").or_else(|| entry.strip_prefix("$ORIGIN"));
+            rest.is_some_and(|rest| !matches!(rest.trim_matches('/'), "" | "."))
+        })
+        .map(String::from)
+        .collect()
+}
+
+/// the patchelf operations for one file, so that they can
+/// all be applied with a single patchelf run
+#[derive(Debug, Default)]
+pub struct PatchOps {
+    pub set_interpreter: Option<String>,
+    pub set_rpath: Option<String>,
+    /// (old, new) pairs of needed entries to rename
+    pub replace_needed: Vec<(String, String)>,
+    /// set DF_1_NODEFLIB, so the loader never searches the default system lib dirs for this file's needed libs
+    pub no_default_lib: bool,
+    /// not an operation: the entries of the rpath the file had before, that were relative to
+    /// $ORIGIN but pointed at another directory, eg: $ORIGIN/../lib. see origin_subdir_entries
+    pub origin_subdirs: Vec<String>,
+}
+
+impl PatchOps {
+    pub fn count(&self) -> usize {
+        self.set_interpreter.iter().count() + self.set_rpath.iter().count()
+            + self.replace_needed.len() + self.no_default_lib as usize
+    }
+
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(interpreter) = &self.set_interpreter {
+            args.push("--set-interpreter".into());
+            args.push(interpreter.clone());
+        }
+        if let Some(rpath) = &self.set_rpath {
+            args.push("--set-rpath".into());
+            args.push(rpath.clone());
+        }
+        for (old, new) in &self.replace_needed {
+            args.push("--replace-needed".into());
+            args.push(old.clone());
+            args.push(new.clone());
+        }
+        if self.no_default_lib {
+            args.push("--no-default-lib".into());
+        }
+        args
+    }
+}
+
+/// runs patchelf once with every operation in ops. does nothing if there are none
+pub fn apply_patch_ops(
+    tools: &ToolConfig,
+    ops: &PatchOps,
+    object_path: &Path,
+) -> Result<(), Error> {
+    if ops.count() == 0 {
+        return Ok(());
+    }
+    let op_args = ops.to_args();
+    let op_args: Vec<&str> = op_args.iter().map(|a| a.as_str()).collect();
+    let output = run_patchelf(tools, &op_args, object_path)?;
+    if output.status != 0 {
+        // patchelf can give error:
+        // cannot find section '.dynamic'. The input file is most likely statically linked
+        // for a statically linked file, which has nothing to patch. ignore this error
+        if output.stderr.contains("statically linked") {
+            return Ok(())
+        }
+        return Err(Error::Patch(format!("Failed to patch loader for {:?}\n{}", object_path, output.stderr)));
+    }
+    Ok(())
+}
+
+/// how patch_loader patches a file, besides pointing it at the loader
+#[derive(Debug, Clone, Default)]
+pub struct LoaderPatch {
+    /// (old, new) pairs of needed entries to rename, eg: an absolute path to its
+    /// basename so that it is loaded from the archive rather than from the host
+    pub replace_needed: Vec<(String, String)>,
+    /// the rpath for a file that is not next to its libs. without one it is . or with append_rpath $ORIGIN
+    pub lib_rpath: Option<String>,
+    /// put the rpath in front of the file's existing rpath so that the bundled
+    /// libs still take precedence, instead of replacing it
+    pub append_rpath: bool,
+    /// set DF_1_NODEFLIB, see PatchOps
+    pub no_default_lib: bool,
+    /// never change the interpreter, only the rest
+    pub keep_interpreter: bool,
+}
+
+/// points the file at the bundled loader, if it has an interpreter at all, by setting it to
+/// interpreter, eg: ./ld-linux-x86-64.so.2. also sets its rpath, and the rest of patch.
+/// the current interpreter and rpath are read first, and only the ones that differ
+/// are changed, so a file that was already patched is left untouched.
+/// returns the operations that were applied
+pub fn patch_loader(
+    tools: &dyn ElfTool,
+    interpreter: &str,
+    object_path: &Path,
+    patch: &LoaderPatch,
+) -> Result<PatchOps, Error> {
+    let lib_rpath = patch.lib_rpath.as_deref();
+    // a statically linked file has no rpath, and nothing else to patch either
+    let current_rpath = match tools.rpath(object_path)? {
+        Some(r) => r,
+        None => return Ok(PatchOps::default()),
+    };
+    let rpath = match (lib_rpath, patch.append_rpath) {
+        (Some(lib_rpath), true) => combine_rpath(lib_rpath, &current_rpath),
+        (Some(lib_rpath), false) => lib_rpath.into(),
+        (None, true) => combine_rpath("$ORIGIN", &current_rpath),
+        (None, false) => ".".into(),
+    };
+    // shared libs have no interpreter, and asking patchelf to set one
+    // would make it fail without setting the rpath either
+    let set_interpreter = match tools.interpreter(object_path)? {
+        Some(current) if !patch.keep_interpreter && current != interpreter => Some(interpreter.to_string()),
+        _ => None,
+    };
+    let ops = PatchOps {
+        set_interpreter,
+        set_rpath: if rpath == current_rpath { None } else { Some(rpath) },
+        replace_needed: patch.replace_needed.clone(),
+        no_default_lib: patch.no_default_lib,
+        // the ones this sets itself, eg: with mirror paths, are where they should be
+        origin_subdirs: origin_subdir_entries(&current_rpath).into_iter()
+            .filter(|entry| !lib_rpath.is_some_and(|lib_rpath| lib_rpath.split(':').any(|e| e == entry)))
+            .collect(),
+    };
+    tools.apply_patch_ops(object_path, &ops)?;
+    Ok(ops)
+}
@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+use serde::{Serialize, Serializer};
+
+/// what happened to a lib that was found during traversal
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LibStatus {
+    Copied,
+    /// left out by an --exclude pattern
+    Excluded,
+    /// copied from the path given with --map, instead of the one ldd found
+    Mapped,
+    /// failed to copy or patch with --best-effort, and so is not in the output
+    Failed,
+    /// the same file is already in the --base-manifest, so it was left out of the output
+    InBase,
+    /// the --target-inventory lists it, so it was left out of the output
+    OnTarget,
+}
+
+/// writes a path as a json string, even one that is not valid utf-8, which serde would refuse
+fn serialize_lossy_path<S: Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => serializer.serialize_str(&path.to_string_lossy()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// one lib in the traversal report
+#[derive(Debug, Clone, Serialize)]
+pub struct LibRecord {
+    pub name: String,
+    /// where the lib was found. None if it was never located
+    #[serde(serialize_with = "serialize_lossy_path")]
+    pub path: Option<PathBuf>,
+    pub status: LibStatus,
+}
+
+/// a json array with an object of name, path and status for every lib
+pub fn make_report_json(records: &[LibRecord]) -> String {
+    let json = serde_json::to_string_pretty(records).expect("a report is only strings");
+    format!("{}\n", json)
+}
+
+/// the totals of a pack run, for --summary-json
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Summary {
+    /// libs in the output, including the loader
+    pub libraries: usize,
+    /// the size of everything copied into the output
+    pub bytes: u64,
+    /// libs that were reached more than once, eg: through a symlink, and so only copied once
+    pub deduplicated: usize,
+    /// libs left out by --exclude or --exclude-path
+    pub excluded: usize,
+    /// libs that failed to copy or patch with --best-effort. failed executables are not counted
+    pub failed: usize,
+    /// libs left out because the --base-manifest has them
+    pub in_base: usize,
+    /// libs left out because the --target-inventory lists them
+    pub on_target: usize,
+    pub elapsed_ms: u128,
+}
+
+/// the summary as a single line json object
+pub fn make_summary_json(summary: &Summary) -> String {
+    serde_json::to_string(summary).expect("a summary is only numbers")
+}
@@ -0,0 +1,148 @@
+use std::{path::Path, collections::HashMap};
+use crate::Error;
+use crate::ldd::SharedLib;
+use crate::deps::DependencyNode;
+use crate::files::sha256_file;
+use serde::Serialize;
+use serde_json::json;
+
+/// the path as it is recorded in the sbom: relative to base if it is under it, else as is.
+/// the path is also compared after following symlinks, since base is usually canonical
+pub fn path_relative_to_base(path: &Path, base: Option<&Path>) -> String {
+    let base = match base {
+        Some(b) => b,
+        None => return path.to_string_lossy().to_string(),
+    };
+    let canonical = std::fs::canonicalize(path).ok();
+    let relative = path.strip_prefix(base).ok()
+        .or_else(|| canonical.as_deref().and_then(|c| c.strip_prefix(base).ok()));
+    match relative {
+        Some(r) => r.to_string_lossy().to_string(),
+        None => path.to_string_lossy().to_string(),
+    }
+}
+
+/// the parts of a CycloneDX document that make_sbom fills in, in the order they are written
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Sbom<'a> {
+    bom_format: &'static str,
+    spec_version: &'static str,
+    version: u32,
+    metadata: serde_json::Value,
+    components: Vec<Component<'a>>,
+    dependencies: Vec<Relationship<'a>>,
+}
+
+#[derive(Serialize)]
+struct Component<'a> {
+    #[serde(rename = "type")]
+    component_type: &'static str,
+    #[serde(rename = "bom-ref")]
+    bom_ref: &'a str,
+    name: &'a str,
+    hashes: serde_json::Value,
+    properties: serde_json::Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Relationship<'a> {
+    #[serde(rename = "ref")]
+    component_ref: &'a str,
+    depends_on: &'a [String],
+}
+
+/// creates a CycloneDX json document that lists every executable, lib, and the loader
+/// as a component, and uses the dependency nodes for the dependency relationships.
+/// source paths under manifest_base are recorded relative to it
+pub fn make_sbom(
+    dependencies: &[DependencyNode],
+    loader: &SharedLib,
+    execnames: &[String],
+    manifest_base: Option<&Path>,
+) -> Result<String, Error> {
+    let mut components = vec![];
+    let mut relationships = vec![];
+    let loader_node = DependencyNode {
+        name: loader.name.clone(),
+        path: loader.path.clone(),
+        ..Default::default()
+    };
+    for dep in dependencies.iter().chain(std::iter::once(&loader_node)) {
+        let component_type = if execnames.contains(&dep.name) { "application" } else { "library" };
+        let hash = sha256_file(&dep.path)?;
+        components.push(Component {
+            component_type,
+            bom_ref: &dep.name,
+            name: &dep.name,
+            hashes: json!([{ "alg": "SHA-256", "content": hash }]),
+            properties: json!([{ "name": "sharedpacker:source-path", "value": path_relative_to_base(&dep.path, manifest_base) }]),
+        });
+        relationships.push(Relationship { component_ref: &dep.name, depends_on: &dep.dependencies });
+    }
+
+    let sbom = Sbom {
+        bom_format: "CycloneDX",
+        spec_version: "1.4",
+        version: 1,
+        metadata: json!({ "tools": [{ "name": "sharedpacker", "version": env!("CARGO_PKG_VERSION") }] }),
+        components,
+        dependencies: relationships,
+    };
+    let json = serde_json::to_string_pretty(&sbom)
+        .map_err(|e| Error::Other(format!("Failed to write the sbom as json: {}", e)))?;
+    Ok(format!("{}\n", json))
+}
+
+/// the SHA-256 of each file in a manifest, by file name. the manifest is either an sbom
+/// made by make_sbom, or a SHA256SUMS file like the one --checksums writes or that
+/// sha256sum can make of a base image's lib dirs. a SHA256SUMS entry is keyed by the
+/// basename of its path, since that is the name a lib is bundled under
+pub fn read_base_manifest(path: &Path) -> Result<HashMap<String, String>, Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| Error::Io(format!("Failed to read {:?}\n{}", path, e)))?;
+    let mut hashes = HashMap::new();
+    if contents.trim_start().starts_with('{') {
+        let sbom: serde_json::Value = serde_json::from_str(&contents)
+            .map_err(|e| Error::Other(format!("Failed to parse sbom {:?}: {}", path, e)))?;
+        let components = sbom["components"].as_array().map(|c| c.as_slice()).unwrap_or_default();
+        for component in components {
+            let hash = component["hashes"].as_array().into_iter().flatten()
+                .find(|h| h["alg"] == "SHA-256")
+                .and_then(|h| h["content"].as_str());
+            if let (Some(name), Some(hash)) = (component["name"].as_str(), hash) {
+                hashes.insert(name.to_string(), hash.to_string());
+            }
+        }
+        return Ok(hashes);
+    }
+    for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+        // sha256sum writes a '*' in front of the path in binary mode
+        let (hash, file) = line.split_once(char::is_whitespace)
+            .map(|(hash, file)| (hash, file.trim_start().trim_start_matches('*')))
+            .ok_or_else(|| Error::Other(format!("Failed to parse {:?}, expected lines of <sha256> <path>: {}", path, line)))?;
+        let name = file.rsplit('/').next().unwrap_or(file);
+        hashes.insert(name.to_string(), hash.to_string());
+    }
+    Ok(hashes)
+}
+
+/// the libs that a target already has, from a --target-inventory file: one lib name per
+/// line, optionally followed by its version, eg: libssl.so.3 3.0.2. blank lines and lines
+/// starting with # are skipped. a lib listed without a version maps to None
+pub fn read_target_inventory(path: &Path) -> Result<HashMap<String, Option<String>>, Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| Error::Io(format!("Failed to read {:?}\n{}", path, e)))?;
+    let mut inventory = HashMap::new();
+    for line in contents.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        let mut fields = line.split_whitespace();
+        let name = fields.next().unwrap_or_default();
+        let version = fields.next().map(|v| v.to_string());
+        if fields.next().is_some() {
+            return Err(Error::Other(format!("Failed to parse {:?}, expected lines of <lib name> [version]: {}", path, line)));
+        }
+        inventory.insert(name.to_string(), version);
+    }
+    Ok(inventory)
+}
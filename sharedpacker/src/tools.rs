@@ -0,0 +1,95 @@
+use std::{path::Path, time::Duration};
+use crate::Error;
+
+/// runs an external command that is not ldd/patchelf, and maps
+/// failing to spawn it to a MissingTool error
+pub fn execute_checked(
+    exe_and_args: &[&str],
+) -> Result<exechelper::CommandOutput, Error> {
+    exechelper::execute(exe_and_args).map_err(|e| tool_error(exe_and_args[0], e))
+}
+
+pub fn tool_error(tool: &str, e: std::io::Error) -> Error {
+    if e.kind() == std::io::ErrorKind::NotFound {
+        Error::MissingTool(format!("Failed to run {}: {}", tool, e))
+    } else {
+        Error::Other(format!("Failed to run {}: {}", tool, e))
+    }
+}
+
+/// settings for how we invoke the external ldd/patchelf tools
+#[derive(Debug, Clone)]
+pub struct ToolConfig {
+    pub ldd: String,
+    pub patchelf: String,
+    pub timeout: Duration,
+    /// read needed libs, interpreters, rpaths and sonames straight from the ELF files
+    /// instead of running patchelf and ldd for them. ldd is still used to find where libs are
+    pub native_elf: bool,
+    /// how many more times to run a tool whose run failed in a way that is_retryable says is transient
+    pub retries: u32,
+}
+
+/// ldd and patchelf from the PATH, with the same timeout as the cli's default
+impl Default for ToolConfig {
+    fn default() -> Self {
+        ToolConfig {
+            ldd: "ldd".into(),
+            patchelf: "patchelf".into(),
+            timeout: Duration::from_secs(60),
+            native_elf: false,
+            retries: 2,
+        }
+    }
+}
+
+/// makes sure a user supplied tool path points at an executable file
+pub fn validate_tool_path(path: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let metadata = std::fs::metadata(path)
+        .map_err(|e| Error::MissingTool(format!("Failed to find tool {:?}\n{}", path, e)))?;
+    if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+        return Err(Error::MissingTool(format!("Tool {:?} is not an executable file", path)));
+    }
+    Ok(())
+}
+
+/// the errno values of failures that tend to go away on their own: a file that is
+/// briefly busy being written (ETXTBSY), and fork failing under load (EAGAIN, ENOMEM)
+const RETRYABLE_ERRNOS: [i32; 3] = [26, 11, 12];
+
+/// whether a failed run might succeed if it is tried again. anything else, like a
+/// missing tool or a lib that is not found, is not going to change by retrying
+pub fn is_retryable(result: &Result<exechelper::CommandOutput, std::io::Error>) -> bool {
+    match result {
+        Err(e) => e.raw_os_error().is_some_and(|code| RETRYABLE_ERRNOS.contains(&code)),
+        Ok(output) if output.status != 0 => is_retryable_message(&output.stderr),
+        Ok(_) => false,
+    }
+}
+
+/// whether a tool's error output is the C locale message of one of the RETRYABLE_ERRNOS
+pub fn is_retryable_message(stderr: &str) -> bool {
+    ["Text file busy", "Resource temporarily unavailable", "Cannot allocate memory"]
+        .iter().any(|m| stderr.contains(m))
+}
+
+/// runs ldd/patchelf with the C locale so that their output and error
+/// messages are always in the wording we parse, regardless of the host locale.
+/// a transient failure is retried up to tools.retries times, waiting twice as long each time
+pub fn execute_tool(
+    tools: &ToolConfig,
+    exe_and_args: &[&str],
+) -> Result<exechelper::CommandOutput, Error> {
+    let mut delay = Duration::from_millis(100);
+    let mut attempt = 0;
+    loop {
+        let result = exechelper::execute_with_env_timeout(exe_and_args, &["LC_ALL", "LANG"], &["C", "C"], tools.timeout);
+        if attempt >= tools.retries || !is_retryable(&result) {
+            return result.map_err(|e| tool_error(exe_and_args[0], e));
+        }
+        attempt += 1;
+        std::thread::sleep(delay);
+        delay *= 2;
+    }
+}
@@ -0,0 +1,42 @@
+use std::path::{Path, PathBuf};
+use crate::Error;
+use crate::tools::execute_checked;
+
+/// makes sure upx can be run before we start copying anything
+pub fn check_upx_installed() -> Result<(), Error> {
+    let output = execute_checked(&["upx", "--version"])
+        .map_err(|e| Error::MissingTool(format!("--upx was given but upx could not be run. is it installed?\n{}", e)))?;
+    if output.status != 0 {
+        return Err(Error::MissingTool(format!("--upx was given but upx --version failed\n{}", output.stderr)));
+    }
+    Ok(())
+}
+
+/// compresses a copied file in place with upx. upx exits nonzero for files
+/// it has already packed or that it cannot make any smaller, which we
+/// treat as leaving the file as it is rather than as a failure.
+/// returns whether the file was compressed
+pub fn compress_with_upx(object_path: &Path) -> Result<bool, Error> {
+    let obj_path_str = object_path.to_string_lossy().to_string();
+    let exec_args = ["upx", "-q", &obj_path_str];
+    let output = execute_checked(&exec_args)?;
+    if output.status != 0 {
+        let message = format!("{}{}", output.stdout, output.stderr);
+        if message.contains("AlreadyPackedException") || message.contains("NotCompressibleException") {
+            return Ok(false);
+        }
+        return Err(Error::Other(format!("Failed to compress {:?} with upx\n{}", object_path, message)));
+    }
+    Ok(true)
+}
+
+/// compresses each of the copied files under the archive, leaving the ones that upx cannot pack as they are
+pub fn compress_files(archive_path: &Path, files: &[PathBuf]) -> Result<(), Error> {
+    for file in files {
+        let path = archive_path.join(file);
+        if !compress_with_upx(&path)? {
+            eprintln!("Leaving {:?} uncompressed because upx could not pack it further", path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use crate::Error;
+
+/// escapes the characters that are special inside a double quoted shell string
+pub fn shell_escape_double_quoted(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '"' | '$' | '`') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// whether name can be used as a shell variable name
+pub fn is_valid_env_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// splits a KEY=VALUE string, making sure KEY is a valid shell variable name
+pub fn parse_env_pair(pair: &str) -> Result<(String, String), Error> {
+    let (key, val) = pair.split_once('=')
+        .ok_or_else(|| Error::Other(format!("Expected KEY=VALUE but got {:?}", pair)))?;
+    if !is_valid_env_name(key) {
+        return Err(Error::Other(format!("{:?} is not a valid environment variable name", key)));
+    }
+    Ok((key.into(), val.into()))
+}
+
+/// what a wrapper script sets up before it launches the executable with the loader
+#[derive(Debug, Clone, Default)]
+pub struct WrapperOptions {
+    /// where the loader and libs live relative to the script, or empty if they are next to it
+    pub lib_dir: String,
+    /// extra variables to export before launching. the loader's --library-path
+    /// always wins over any LD_LIBRARY_PATH set this way
+    pub env: Vec<(String, String)>,
+    /// variables to export as the path of their value relative to the script, eg: GCONV_PATH=gconv
+    pub bundle_paths: Vec<(String, String)>,
+    /// if not empty, the executable is started with env -i and only gets those of these
+    /// variables that are set, along with the ones the script exports itself
+    pub passthrough: Vec<String>,
+}
+
+/// the script that launches execname with the bundled loader loadername
+pub fn make_shell_script_wrapper(
+    execname: &str,
+    loadername: &str,
+    opts: &WrapperOptions,
+) -> String {
+    let (lib_dir, env, bundle_paths, passthrough) = (&opts.lib_dir, &opts.env, &opts.bundle_paths, &opts.passthrough);
+    // https://stackoverflow.com/a/4774063
+    // the script is resolved first, so that it also works when run through a symlink
+    let part_one: String = "#!/usr/bin/env bash\n\nSCRIPTPATH=\"$( cd -- \"$(dirname \"$(readlink -f \"$0\")\")\" >/dev/null 2>&1 ; pwd -P )\"".into();
+    let mut exports = String::new();
+    for (key, val) in env {
+        exports.push_str(&format!("export {}=\"{}\"\n", key, shell_escape_double_quoted(val)));
+    }
+    for (key, relative) in bundle_paths {
+        exports.push_str(&format!("export {}=\"$SCRIPTPATH/{}\"\n", key, shell_escape_double_quoted(relative)));
+    }
+    // SCRIPTPATH is only ever expanded inside double quotes, so a space in it is
+    // fine. the names are escaped, since they end up inside those quotes too
+    let lib_path = if lib_dir.is_empty() {
+        "$SCRIPTPATH".to_string()
+    } else {
+        format!("$SCRIPTPATH/{}", shell_escape_double_quoted(lib_dir.trim_end_matches('/')))
+    };
+    // the names are valid shell variable names, so they need no quoting
+    let scrub = if passthrough.is_empty() {
+        String::new()
+    } else {
+        let mut names: Vec<&str> = passthrough.iter().map(|n| n.as_str()).collect();
+        names.extend(env.iter().chain(bundle_paths).map(|(key, _)| key.as_str()));
+        exports.push_str(&format!("PASSTHROUGH=()\nfor var in {}; do\n    \
+            if [ -n \"${{!var+x}}\" ]; then PASSTHROUGH+=(\"$var=${{!var}}\"); fi\ndone\n", names.join(" ")));
+        "env -i \"${PASSTHROUGH[@]}\" ".to_string()
+    };
+    let part_two = format!("{}\"{}/{}\" --library-path \"{}\" \"$SCRIPTPATH/{}\" \"$@\"",
+        scrub, lib_path, shell_escape_double_quoted(loadername), lib_path, shell_escape_double_quoted(execname));
+    let out = format!("{}\n{}{}", part_one, exports, part_two);
+    out
+}
@@ -0,0 +1,123 @@
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// a directory under the system temp dir that is removed when dropped
+pub struct TempDir {
+    pub path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir()
+            .join(format!("sharedpacker-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// the pack tests need to run ldd, patchelf, and a C compiler.
+/// returns false (after saying why) if any of them are missing
+pub fn tools_available() -> bool {
+    for tool in ["ldd", "patchelf", "cc"] {
+        let found = Command::new("sh")
+            .args(["-c", &format!("command -v {}", tool)])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+        if !found {
+            eprintln!("skipping: {} is not available", tool);
+            return false;
+        }
+    }
+    true
+}
+
+pub fn cc(args: &[&str], dir: &Path) {
+    let out = Command::new("cc").args(args).current_dir(dir).output().unwrap();
+    assert!(out.status.success(), "cc {:?} failed:\n{}", args, String::from_utf8_lossy(&out.stderr));
+}
+
+/// builds dir/sample, which needs libtwo.so, which in turn needs libone.so.
+/// running it prints "2"
+pub fn build_sample(dir: &Path) -> PathBuf {
+    std::fs::write(dir.join("one.c"), "int one(void) { return 1; }\n").unwrap();
+    std::fs::write(dir.join("two.c"), "int one(void);\nint two(void) { return one() + 1; }\n").unwrap();
+    std::fs::write(dir.join("sample.c"),
+        "#include <stdio.h>\nint two(void);\nint main(void) { printf(\"%d\\n\", two()); return 0; }\n").unwrap();
+    let rpath = format!("-Wl,-rpath,{}", dir.display());
+    cc(&["-shared", "-fPIC", "-o", "libone.so", "one.c"], dir);
+    cc(&["-shared", "-fPIC", "-o", "libtwo.so", "two.c", "-L.", "-lone", &rpath], dir);
+    cc(&["-o", "sample", "sample.c", "-L.", "-ltwo", "-lone", &rpath], dir);
+    dir.join("sample")
+}
+
+pub fn sharedpacker(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_sharedpacker")).args(args).output().unwrap()
+}
+
+pub fn assert_success(out: &Output) {
+    assert!(out.status.success(), "exited with {:?}\nstderr:\n{}",
+        out.status.code(), String::from_utf8_lossy(&out.stderr));
+}
+
+/// builds dir/<name> from a program that only needs libc. running it prints name
+pub fn build_plain(dir: &Path, name: &str) -> PathBuf {
+    let src = format!("{}.c", name);
+    std::fs::write(dir.join(&src),
+        format!("#include <stdio.h>\nint main(void) {{ puts(\"{}\"); return 0; }}\n", name)).unwrap();
+    cc(&["-o", name, &src], dir);
+    dir.join(name)
+}
+
+/// like build_plain, but the executable's interpreter is the given path instead of the system loader
+pub fn build_with_interpreter(dir: &Path, name: &str, interpreter: &Path) -> PathBuf {
+    let src = format!("{}.c", name);
+    std::fs::write(dir.join(&src),
+        format!("#include <stdio.h>\nint main(void) {{ puts(\"{}\"); return 0; }}\n", name)).unwrap();
+    let linker_flag = format!("-Wl,--dynamic-linker={}", interpreter.display());
+    cc(&["-o", name, &src, &linker_flag], dir);
+    dir.join(name)
+}
+
+/// builds dir/absneeded, which needs dir/libabs.so by its absolute path, because
+/// libabs.so has no soname and is linked in by path. running it prints "7"
+pub fn build_absolute_needed(dir: &Path) -> PathBuf {
+    std::fs::write(dir.join("abs.c"), "int abs_seven(void) { return 7; }\n").unwrap();
+    std::fs::write(dir.join("absneeded.c"),
+        "#include <stdio.h>\nint abs_seven(void);\nint main(void) { printf(\"%d\\n\", abs_seven()); return 0; }\n").unwrap();
+    cc(&["-shared", "-fPIC", "-o", "libabs.so", "abs.c"], dir);
+    let lib = dir.join("libabs.so");
+    cc(&["-o", "absneeded", "absneeded.c", lib.to_str().unwrap()], dir);
+    dir.join("absneeded")
+}
+
+/// copies every lib that ldd reports for exe into root/real, and links each of the
+/// paths that ldd reported to it under root with an absolute symlink, like a rootfs that
+/// was extracted somewhere. the loader is too. the returned path is where the files are, root/real
+pub fn build_sysroot(root: &Path, exe: &Path) -> PathBuf {
+    use sharedpacker::{tools::ToolConfig, ldd::{parse_ldd_output, get_loader}};
+    let tools = ToolConfig::default();
+    let real = root.join("real");
+    std::fs::create_dir_all(&real).unwrap();
+    for lib in parse_ldd_output(&tools, exe).unwrap() {
+        std::fs::copy(&lib.path, real.join(&lib.name)).unwrap();
+        let link = root.join(lib.path.strip_prefix("/").unwrap());
+        std::fs::create_dir_all(link.parent().unwrap()).unwrap();
+        std::os::unix::fs::symlink(Path::new("/real").join(&lib.name), &link).unwrap();
+    }
+    let loader = get_loader(&tools, exe).unwrap();
+    std::fs::copy(&loader.path, real.join(&loader.name)).unwrap();
+    let link = root.join(loader.path.strip_prefix("/").unwrap());
+    std::fs::create_dir_all(link.parent().unwrap()).unwrap();
+    std::os::unix::fs::symlink(Path::new("/real").join(&loader.name), &link).unwrap();
+    real
+}
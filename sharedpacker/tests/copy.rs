@@ -0,0 +1,114 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::*;
+use sharedpacker::copy::{CopyOptions, PatchStrategy, copy_dependencies_to_output_folder, mirrored_rpath};
+use sharedpacker::elf::read_needed;
+use sharedpacker::deps::DependencyNode;
+use sharedpacker::filter::LibFilter;
+use sharedpacker::ldd::{SharedLib, get_loader};
+use sharedpacker::tools::ToolConfig;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[test]
+fn wrapper_invokes_the_loader_under_its_copied_name() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("copy-loader-name");
+    let exe = build_plain(&tmp.path, "plain");
+    let tools = ToolConfig::default();
+
+    // reference the real loader through a symlink of another name, so that
+    // --loader-symlink copies it under a name the executable never mentions
+    let real_loader = get_loader(&tools, &exe).unwrap();
+    let real_name = std::fs::canonicalize(&real_loader.path).unwrap()
+        .file_name().unwrap().to_string_lossy().to_string();
+    let renamed = tmp.path.join("ld-renamed.so");
+    std::os::unix::fs::symlink(&real_loader.path, &renamed).unwrap();
+    let loader = SharedLib { name: "ld-renamed.so".into(), path: renamed };
+
+    let output = tmp.path.join("out");
+    let deps = vec![DependencyNode { name: "plain".into(), path: exe, ..Default::default() }];
+    let opts = CopyOptions { make_wrapper: true, loader_symlink: true, ..Default::default() };
+    copy_dependencies_to_output_folder(&tools, &output, &deps, &loader, &["plain".into()], &opts).unwrap();
+
+    let wrapper = std::fs::read_to_string(output.join("plain")).unwrap();
+    let launch = wrapper.lines().last().unwrap();
+    assert!(launch.starts_with(&format!("\"$SCRIPTPATH/{}\"", real_name)), "unexpected launch line: {}", launch);
+    assert!(!output.join(&real_name).symlink_metadata().unwrap().file_type().is_symlink());
+
+    let run = Command::new(output.join("plain")).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "plain\n");
+}
+
+#[test]
+fn mirrored_rpath_reaches_every_lib_dir_from_the_file() {
+    let lib_dirs = vec![PathBuf::from("usr/lib"), PathBuf::from("opt/app/lib")];
+    assert_eq!(mirrored_rpath(Path::new(""), &lib_dirs), "$ORIGIN/usr/lib:$ORIGIN/opt/app/lib");
+    assert_eq!(mirrored_rpath(Path::new("usr/lib"), &lib_dirs), "$ORIGIN/../../usr/lib:$ORIGIN/../../opt/app/lib");
+    assert_eq!(mirrored_rpath(Path::new("usr/lib"), &[PathBuf::new()]), "$ORIGIN/../..");
+}
+
+#[test]
+fn a_filter_leaves_out_the_libs_it_rejects() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("copy-filter");
+    let exe = build_sample(&tmp.path);
+    let tools = ToolConfig::default();
+    let loader = get_loader(&tools, &exe).unwrap();
+    let deps = vec![
+        DependencyNode { name: "sample".into(), path: exe, ..Default::default() },
+        DependencyNode { name: "libone.so".into(), path: tmp.path.join("libone.so"), ..Default::default() },
+        DependencyNode { name: "libtwo.so".into(), path: tmp.path.join("libtwo.so"), ..Default::default() },
+    ];
+    let output = tmp.path.join("out");
+    let opts = CopyOptions {
+        filter: Some(LibFilter::new(|dep| dep.name != "libtwo.so")),
+        ..Default::default()
+    };
+    let report = copy_dependencies_to_output_folder(&tools, &output, &deps, &loader, &["sample".into()], &opts).unwrap();
+
+    assert_eq!(report.filtered, ["libtwo.so"]);
+    assert!(output.join("libone.so").is_file());
+    assert!(!output.join("libtwo.so").exists());
+    assert!(output.join("sample").is_file(), "the filter is not asked about executables");
+}
+
+#[test]
+fn mirrored_libs_of_the_same_name_are_patched_apart() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("copy-mirror-scratch");
+    let exe = build_sample(&tmp.path);
+    let tools = ToolConfig::default();
+    let loader = get_loader(&tools, &exe).unwrap();
+    // two different files that are both libone.so, in different directories
+    for (dir, source) in [("a", "libone.so"), ("b", "libtwo.so")] {
+        std::fs::create_dir(tmp.path.join(dir)).unwrap();
+        std::fs::copy(tmp.path.join(source), tmp.path.join(dir).join("libone.so")).unwrap();
+    }
+    let deps = vec![
+        DependencyNode { name: "libone.so".into(), path: tmp.path.join("a/libone.so"), ..Default::default() },
+        DependencyNode { name: "libone.so".into(), path: tmp.path.join("b/libone.so"), ..Default::default() },
+    ];
+    let output = tmp.path.join("out");
+    let opts = CopyOptions {
+        mirror_paths: true,
+        patch_strategy: PatchStrategy::TempThenMove,
+        copy_jobs: 4,
+        patch_jobs: 4,
+        ..Default::default()
+    };
+    copy_dependencies_to_output_folder(&tools, &output, &deps, &loader, &[], &opts).unwrap();
+
+    let mirrored = output.join(tmp.path.strip_prefix("/").unwrap());
+    assert!(read_needed(&mirrored.join("a/libone.so")).unwrap().is_empty());
+    assert!(read_needed(&mirrored.join("b/libone.so")).unwrap().contains(&"libone.so".to_string()));
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::TempDir;
+use sharedpacker::tools::ToolConfig;
+use sharedpacker::deps::{DependencyNode, ResolveOptions, find_unreachable, dedupe_by_path, resolve_dependencies};
+
+fn node(name: &str, dependencies: &[&str]) -> DependencyNode {
+    DependencyNode {
+        name: name.into(),
+        path: format!("/lib/{}", name).into(),
+        dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn lists_nodes_that_no_root_reaches() {
+    let nodes = vec![
+        node("libone.so", &[]),
+        node("libtwo.so", &["libone.so"]),
+        node("libextra.so", &["libone.so"]),
+        node("sample", &["libtwo.so"]),
+    ];
+    assert_eq!(find_unreachable(&nodes, &["sample".into()]), vec!["libextra.so"]);
+    assert!(find_unreachable(&nodes, &["sample".into(), "libextra.so".into()]).is_empty());
+}
+
+#[test]
+fn keeps_one_node_per_file() {
+    let tmp = TempDir::new("deps-dedupe");
+    let real = tmp.path.join("libone.so.1");
+    std::fs::write(&real, "").unwrap();
+    let other_dir = tmp.path.join("other");
+    std::fs::create_dir(&other_dir).unwrap();
+    std::os::unix::fs::symlink(&real, other_dir.join("libone.so.1")).unwrap();
+    std::os::unix::fs::symlink("libone.so.1", tmp.path.join("libone.so")).unwrap();
+
+    let nodes = vec![
+        DependencyNode { name: "libone.so.1".into(), path: real, dependencies: vec!["libc.so.6".into()], ..Default::default() },
+        node("libtwo.so", &[]),
+        DependencyNode { name: "libone.so.1".into(), path: other_dir.join("libone.so.1"), dependencies: vec!["libm.so.6".into()], ..Default::default() },
+        // the same file under another name, which stays in the archive as an alias
+        DependencyNode { name: "libone.so".into(), path: tmp.path.join("libone.so"), dependencies: vec![], ..Default::default() },
+    ];
+    let deduped = dedupe_by_path(nodes);
+    let names: Vec<&str> = deduped.iter().map(|n| n.name.as_str()).collect();
+    assert_eq!(names, vec!["libone.so.1", "libtwo.so"]);
+    assert_eq!(deduped[0].dependencies, vec!["libc.so.6", "libm.so.6"]);
+    assert_eq!(deduped[0].aliases, vec!["libone.so"]);
+}
+
+#[test]
+fn resolves_an_executable_without_touching_the_filesystem() {
+    if !common::tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("deps-resolve");
+    let sample = common::build_sample(&tmp.path);
+    let before = std::fs::read_dir(&tmp.path).unwrap().count();
+
+    let (nodes, loader) = resolve_dependencies(&ToolConfig::default(), &sample, &ResolveOptions::default()).unwrap();
+    let names: Vec<&str> = nodes.iter().map(|n| n.name.as_str()).collect();
+    for name in ["sample", "libtwo.so", "libone.so", "libc.so.6"] {
+        assert!(names.contains(&name), "{} was not resolved: {:?}", name, names);
+    }
+    assert!(loader.name.starts_with("ld-"), "unexpected loader {:?}", loader);
+    assert_eq!(std::fs::read_dir(&tmp.path).unwrap().count(), before);
+}
+
+#[test]
+fn resolves_the_absolute_symlinks_of_a_sysroot_inside_it() {
+    if !common::tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("deps-sysroot");
+    let sample = common::build_sample(&tmp.path);
+    let root = tmp.path.join("root");
+    let real = std::fs::canonicalize(common::build_sysroot(&root, &sample)).unwrap();
+
+    let opts = ResolveOptions { sysroot: Some(root), ..Default::default() };
+    let (nodes, loader) = resolve_dependencies(&ToolConfig::default(), &sample, &opts).unwrap();
+    for node in nodes.iter().filter(|n| n.name != "sample") {
+        assert_eq!(node.path, real.join(&node.name), "{} is not the one in the sysroot", node.name);
+    }
+    assert_eq!(loader.path, real.join(&loader.name), "the loader is not the one in the sysroot");
+}
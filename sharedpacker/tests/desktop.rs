@@ -0,0 +1,14 @@
+use sharedpacker::desktop::make_desktop_entry;
+
+#[test]
+fn points_exec_at_the_wrapper() {
+    let entry = make_desktop_entry("My App", "myapp", Some("myapp"));
+    assert_eq!(entry, "[Desktop Entry]\nType=Application\nName=My App\nExec=myapp\nIcon=myapp\nTerminal=false\n");
+}
+
+#[test]
+fn quotes_an_exec_with_reserved_characters() {
+    let entry = make_desktop_entry("app", "my $app", None);
+    assert!(entry.contains("Exec=\"my \\\\$app\"\n"), "{}", entry);
+    assert!(!entry.contains("Icon="));
+}
@@ -0,0 +1,41 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::*;
+
+#[test]
+fn doctor_passes_with_the_installed_tools() {
+    if !tools_available() {
+        return;
+    }
+    let out = sharedpacker(&["doctor"]);
+    assert_success(&out);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("ldd: ") && stdout.contains("patchelf: "), "{}", stdout);
+}
+
+#[test]
+fn doctor_fails_on_a_patchelf_without_the_needed_flags() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("doctor-old-patchelf");
+    let fake = tmp.path.join("patchelf");
+    std::fs::write(&fake, "#!/bin/sh\n[ \"$1\" = --version ] && echo 'patchelf 0.1' && exit 0\necho \"unknown option $1\" >&2\nexit 1\n").unwrap();
+    let chmod = std::process::Command::new("chmod").args(["+x", fake.to_str().unwrap()]).output().unwrap();
+    assert_success(&chmod);
+
+    let out = sharedpacker(&["doctor", "--patchelf-path", fake.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("patchelf: problem") && stdout.contains("may be too old"), "{}", stdout);
+}
+
+#[test]
+fn doctor_fails_when_a_required_tool_is_missing() {
+    let out = sharedpacker(&["doctor", "--require", "sharedpacker-no-such-tool"]);
+    assert!(!out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("sharedpacker-no-such-tool: problem"), "{}", stdout);
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::TempDir;
+use sharedpacker::elf::{read_elf_arch, has_interpreter, read_needed, read_interpreter, read_rpath, read_build_id};
+
+#[test]
+fn reads_class_and_machine_from_the_header() {
+    let tmp = TempDir::new("elf-header");
+    let path = tmp.path.join("fake.so");
+    let mut header = vec![0u8; 20];
+    header[0..4].copy_from_slice(b"\x7fELF");
+    header[4] = 1; // 32 bit
+    header[5] = 1; // little endian
+    header[18] = 3; // x86
+    std::fs::write(&path, &header).unwrap();
+
+    let arch = read_elf_arch(&path).unwrap();
+    assert_eq!((arch.class, arch.machine), (1, 3));
+    assert_eq!(arch.to_string(), "32-bit x86");
+}
+
+#[test]
+fn rejects_files_that_are_not_elf() {
+    let tmp = TempDir::new("elf-not-elf");
+    let path = tmp.path.join("script.sh");
+    std::fs::write(&path, "#!/bin/sh\necho this is not an ELF file\n").unwrap();
+    assert!(read_elf_arch(&path).is_err());
+}
+
+#[test]
+fn a_corrupt_program_header_table_is_an_error() {
+    let tmp = TempDir::new("elf-corrupt");
+    let path = tmp.path.join("corrupt.so");
+    let mut header = vec![0u8; 64];
+    header[0..4].copy_from_slice(b"\x7fELF");
+    header[4] = 2; // 64 bit
+    header[5] = 1; // little endian
+    header[6] = 1;
+    header[32] = 64; // the program headers start right after this header
+    // far more and far bigger program headers than the file has room for
+    header[54..58].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+    std::fs::write(&path, &header).unwrap();
+    assert!(has_interpreter(&path).is_err());
+}
+
+#[test]
+fn executables_have_an_interpreter_and_libs_do_not() {
+    if !common::tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("elf-interp");
+    common::build_sample(&tmp.path);
+    assert!(has_interpreter(&tmp.path.join("sample")).unwrap());
+    assert!(!has_interpreter(&tmp.path.join("libone.so")).unwrap());
+}
+
+#[test]
+fn reads_needed_libs_and_the_interpreter_natively() {
+    if !common::tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("elf-native");
+    let sample = common::build_sample(&tmp.path);
+
+    let needed = read_needed(&sample).unwrap();
+    assert!(needed.contains(&"libtwo.so".to_string()), "{:?}", needed);
+    assert!(needed.contains(&"libc.so.6".to_string()), "{:?}", needed);
+    let interpreter = read_interpreter(&sample).unwrap().expect("sample has no interpreter");
+    assert!(interpreter.rsplit('/').next().unwrap().starts_with("ld-"), "{}", interpreter);
+    assert_eq!(read_interpreter(&tmp.path.join("libone.so")).unwrap(), None);
+    assert_eq!(read_rpath(&sample).unwrap().as_deref(), Some(tmp.path.to_str().unwrap()));
+    assert_eq!(read_rpath(&tmp.path.join("libone.so")).unwrap().as_deref(), Some(""));
+}
+
+#[test]
+fn reads_the_gnu_build_id() {
+    if !common::tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("elf-build-id");
+    std::fs::write(tmp.path.join("main.c"), "int main(void) { return 0; }\n").unwrap();
+    common::cc(&["-o", "with-id", "main.c", "-Wl,--build-id=0x0123456789abcdef"], &tmp.path);
+    common::cc(&["-o", "without-id", "main.c", "-Wl,--build-id=none"], &tmp.path);
+
+    assert_eq!(read_build_id(&tmp.path.join("with-id")).unwrap().as_deref(), Some("0123456789abcdef"));
+    assert_eq!(read_build_id(&tmp.path.join("without-id")).unwrap(), None);
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::Mutex};
+use common::TempDir;
+use sharedpacker::Error;
+use sharedpacker::deps::{DependencyNode, TraverseOptions, traverse_dependencies, prune_duplicate_sonames};
+use sharedpacker::elftool::ElfTool;
+use sharedpacker::ldd::SharedLib;
+use sharedpacker::patch::{LoaderPatch, PatchOps, patch_loader};
+
+/// resolves made up files from a table instead of running ldd and patchelf
+struct FakeElfTool {
+    /// file name to the names it needs
+    needed: HashMap<String, Vec<String>>,
+    dir: PathBuf,
+    interpreters: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl FakeElfTool {
+    fn name(path: &Path) -> String {
+        path.file_name().unwrap().to_string_lossy().to_string()
+    }
+}
+
+impl ElfTool for FakeElfTool {
+    fn list_libs(&self, path: &Path) -> Result<Vec<SharedLib>, Error> {
+        // like ldd, every lib in the closure, not only the direct ones
+        let mut libs: Vec<SharedLib> = vec![];
+        let mut queue = self.needed(path)?;
+        while let Some(name) = queue.pop() {
+            if libs.iter().any(|l| l.name == name) {
+                continue;
+            }
+            queue.extend(self.needed(&self.dir.join(&name))?);
+            libs.push(SharedLib { path: self.dir.join(&name), name });
+        }
+        Ok(libs)
+    }
+
+    fn needed(&self, path: &Path) -> Result<Vec<String>, Error> {
+        Ok(self.needed.get(&Self::name(path)).cloned().unwrap_or_default())
+    }
+
+    fn interpreter(&self, path: &Path) -> Result<Option<String>, Error> {
+        Ok(self.interpreters.lock().unwrap().get(path).cloned())
+    }
+
+    fn rpath(&self, _path: &Path) -> Result<Option<String>, Error> {
+        Ok(Some(String::new()))
+    }
+
+    /// a lib's soname is its name up to the first version number
+    fn soname(&self, path: &Path) -> Result<Option<String>, Error> {
+        Ok(Self::name(path).split_once(".so").map(|(stem, _)| format!("{}.so", stem)))
+    }
+
+    fn run(&self, exe_and_args: &[&str]) -> Result<exechelper::CommandOutput, Error> {
+        Err(Error::Other(format!("Cannot run {:?} with a fake", exe_and_args)))
+    }
+
+    fn set_interpreter(&self, path: &Path, interpreter: &str) -> Result<(), Error> {
+        self.interpreters.lock().unwrap().insert(path.into(), interpreter.into());
+        Ok(())
+    }
+
+    fn set_rpath(&self, _path: &Path, _rpath: &str) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn traverses_with_an_injected_elf_tool() {
+    let tmp = TempDir::new("elftool-traverse");
+    for name in ["app", "libfoo.so", "libbar.so"] {
+        std::fs::write(tmp.path.join(name), "").unwrap();
+    }
+    let tool = FakeElfTool {
+        needed: HashMap::from([
+            ("app".to_string(), vec!["libfoo.so".to_string()]),
+            ("libfoo.so".to_string(), vec!["libbar.so".to_string()]),
+        ]),
+        dir: tmp.path.clone(),
+        interpreters: Mutex::new(HashMap::new()),
+    };
+
+    let mut dependencies = vec![];
+    traverse_dependencies(&tool, &mut HashMap::new(), &mut vec![], &mut dependencies,
+        &tmp.path.join("app"), "app", &TraverseOptions::default()).unwrap();
+    let names: Vec<&str> = dependencies.iter().map(|d| d.name.as_str()).collect();
+    assert_eq!(names, ["libbar.so", "libfoo.so", "app"]);
+    assert_eq!(dependencies[1].dependencies, ["libbar.so"]);
+}
+
+#[test]
+fn the_default_apply_only_sets_the_interpreter_and_rpath() {
+    let tmp = TempDir::new("elftool-apply");
+    let path = tmp.path.join("lib.so");
+    let tool = FakeElfTool { needed: HashMap::new(), dir: tmp.path.clone(), interpreters: Mutex::new(HashMap::new()) };
+
+    let ops = PatchOps { set_interpreter: Some("./ld.so".into()), ..Default::default() };
+    tool.apply_patch_ops(&path, &ops).unwrap();
+    assert_eq!(tool.interpreter(&path).unwrap().as_deref(), Some("./ld.so"));
+
+    let ops = PatchOps { replace_needed: vec![("/a/liba.so".into(), "liba.so".into())], ..Default::default() };
+    assert!(tool.apply_patch_ops(&path, &ops).is_err());
+}
+
+#[test]
+fn patch_loader_leaves_alone_what_is_already_patched() {
+    let tmp = TempDir::new("elftool-patch");
+    let exe = tmp.path.join("plain");
+    let tool = FakeElfTool { needed: HashMap::new(), dir: tmp.path.clone(), interpreters: Mutex::new(HashMap::new()) };
+    tool.set_interpreter(&exe, "./ld.so").unwrap();
+
+    let ops = patch_loader(&tool, "./ld.so", &exe, &LoaderPatch::default()).unwrap();
+    assert_eq!(ops.set_interpreter, None);
+    assert_eq!(ops.set_rpath.as_deref(), Some("."));
+
+    // without an interpreter, eg: a shared lib, none is set
+    let ops = patch_loader(&tool, "./ld.so", &tmp.path.join("lib.so"), &LoaderPatch::default()).unwrap();
+    assert_eq!(ops.set_interpreter, None);
+}
+
+#[test]
+fn prunes_duplicate_sonames_with_an_injected_elf_tool() {
+    let node = |name: &str, dependencies: &[&str]| DependencyNode {
+        name: name.into(),
+        path: PathBuf::from("/fake").join(name),
+        dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+        ..Default::default()
+    };
+    let tool = FakeElfTool { needed: HashMap::new(), dir: "/fake".into(), interpreters: Mutex::new(HashMap::new()) };
+    let mut nodes = vec![node("libfoo.so.1", &[]), node("libfoo.so.1.2", &[]), node("app", &["libfoo.so.1.2"])];
+
+    let pruned = prune_duplicate_sonames(&tool, &mut nodes, &["app".to_string()]).unwrap();
+    assert_eq!(pruned, [("libfoo.so.1.2".to_string(), "libfoo.so.1".to_string())]);
+    assert_eq!(nodes[1].dependencies, ["libfoo.so.1"]);
+}
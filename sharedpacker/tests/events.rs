@@ -0,0 +1,13 @@
+use sharedpacker::events::make_event_json;
+
+#[test]
+fn events_are_single_line_json_objects() {
+    let line = make_event_json("copied", Some("lib\"odd\".so"), 1700000000123);
+    assert!(!line.contains('\n'));
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(value["event"], "copied");
+    assert_eq!(value["name"], "lib\"odd\".so");
+    assert_eq!(value["timestamp_ms"], 1700000000123u64);
+    let done: serde_json::Value = serde_json::from_str(&make_event_json("done", None, 0)).unwrap();
+    assert!(done["name"].is_null());
+}
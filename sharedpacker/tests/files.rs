@@ -0,0 +1,137 @@
+mod common;
+
+use common::TempDir;
+use sharedpacker::files::{unusual_permissions, normalize_output_path, parse_size, parse_mode, check_output_is_not_a_source_dir, check_output_writable, create_output_dir, expand_exe_dirs, stamp_output_path, canonicalize_in_sysroot};
+use std::os::unix::fs::PermissionsExt;
+
+#[test]
+fn flags_world_writable_and_setuid_files() {
+    let tmp = TempDir::new("files-perms");
+    let path = tmp.path.join("libodd.so");
+    std::fs::write(&path, "").unwrap();
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(unusual_permissions(&path).is_empty());
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o4757)).unwrap();
+    assert_eq!(unusual_permissions(&path), vec!["setuid", "world-writable"]);
+}
+
+#[test]
+fn normalizes_the_output_path_through_its_parent() {
+    let tmp = TempDir::new("files-normalize");
+    std::fs::create_dir(tmp.path.join("sub")).unwrap();
+    let direct = normalize_output_path(&tmp.path.join("out")).unwrap();
+    let roundabout = normalize_output_path(&tmp.path.join("sub/../out")).unwrap();
+    assert_eq!(direct, roundabout);
+    assert!(direct.is_absolute());
+    assert_eq!(normalize_output_path(std::path::Path::new("out")).unwrap(),
+        std::env::current_dir().unwrap().canonicalize().unwrap().join("out"));
+}
+
+#[test]
+fn parses_octal_modes() {
+    assert_eq!(parse_mode("755").unwrap(), 0o755);
+    assert_eq!(parse_mode("0750").unwrap(), 0o750);
+    assert!(parse_mode("rwx").is_err());
+    assert!(parse_mode("789").is_err());
+    assert!(parse_mode("17777").is_err());
+}
+
+#[test]
+fn parses_sizes_with_binary_suffixes() {
+    assert_eq!(parse_size("1048576").unwrap(), 1048576);
+    assert_eq!(parse_size("512K").unwrap(), 512 * 1024);
+    assert_eq!(parse_size("1.5M").unwrap(), 1536 * 1024);
+    assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+    assert_eq!(parse_size("10mb").unwrap(), 10 << 20);
+    assert!(parse_size("ten megs").is_err());
+    assert!(parse_size("10X").is_err());
+}
+
+#[test]
+fn refuses_an_output_that_holds_a_source_file() {
+    let tmp = TempDir::new("files-source-dir");
+    let lib_dir = tmp.path.join("lib");
+    std::fs::create_dir(&lib_dir).unwrap();
+    let lib = lib_dir.join("libone.so");
+    std::fs::write(&lib, "").unwrap();
+    let sources = [lib.as_path()];
+
+    assert!(check_output_is_not_a_source_dir(&lib_dir, sources).is_err());
+    assert!(check_output_is_not_a_source_dir(&tmp.path, sources).is_err());
+    assert!(check_output_is_not_a_source_dir(&lib_dir.join("out"), sources).is_ok());
+    assert!(check_output_is_not_a_source_dir(&tmp.path.join("li"), sources).is_ok());
+}
+
+#[test]
+fn probing_a_new_output_leaves_nothing_behind() {
+    let tmp = TempDir::new("files-writable");
+    let output = tmp.path.join("a/b/out");
+    check_output_writable(&output).unwrap();
+    assert!(!tmp.path.join("a").exists());
+
+    std::fs::create_dir(tmp.path.join("existing")).unwrap();
+    check_output_writable(&tmp.path.join("existing")).unwrap();
+    assert_eq!(std::fs::read_dir(tmp.path.join("existing")).unwrap().count(), 0);
+}
+
+#[test]
+fn an_output_under_a_file_names_the_file() {
+    let tmp = TempDir::new("files-parent-is-file");
+    let file = tmp.path.join("file");
+    std::fs::write(&file, "").unwrap();
+    for output in [file.join("out"), file.join("a/out")] {
+        let err = create_output_dir(&output).unwrap_err().to_string();
+        assert!(err.contains(&format!("{:?} is a file, not a directory", file)), "{}", err);
+        let err = check_output_writable(&output).unwrap_err().to_string();
+        assert!(err.contains(&format!("{:?} is a file", file)), "{}", err);
+    }
+}
+
+#[test]
+fn a_directory_expands_to_the_elf_executables_in_it() {
+    let tmp = TempDir::new("exe-dirs");
+    let bin = tmp.path.join("bin");
+    std::fs::create_dir_all(bin.join("nested")).unwrap();
+    // the test binary itself is a handy executable ELF file
+    let elf = std::env::current_exe().unwrap();
+    std::fs::copy(&elf, bin.join("tool")).unwrap();
+    std::fs::copy(&elf, bin.join("nested").join("inner")).unwrap();
+    std::fs::write(bin.join("script.sh"), "#!/bin/sh\n").unwrap();
+    std::fs::set_permissions(bin.join("script.sh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::fs::copy(&elf, bin.join("not-executable")).unwrap();
+    std::fs::set_permissions(bin.join("not-executable"), std::fs::Permissions::from_mode(0o644)).unwrap();
+    let other = tmp.path.join("other");
+
+    let found = expand_exe_dirs(&[bin.clone(), other.clone()], false, false).unwrap();
+    assert_eq!(found, [bin.join("tool"), other.clone()]);
+    let found = expand_exe_dirs(std::slice::from_ref(&bin), true, false).unwrap();
+    assert_eq!(found, [bin.join("nested").join("inner"), bin.join("tool")]);
+}
+
+#[test]
+fn stamps_go_before_a_squashfs_extension() {
+    assert_eq!(stamp_output_path(std::path::Path::new("/tmp/out"), "1.2.0"), std::path::Path::new("/tmp/out-1.2.0"));
+    assert_eq!(stamp_output_path(std::path::Path::new("/tmp/out.squashfs"), "1.2.0"), std::path::Path::new("/tmp/out-1.2.0.squashfs"));
+}
+
+#[test]
+fn follows_symlinks_within_the_sysroot() {
+    let tmp = TempDir::new("files-sysroot");
+    let root = tmp.path.join("root");
+    std::fs::create_dir_all(root.join("usr/lib")).unwrap();
+    std::fs::create_dir_all(root.join("lib")).unwrap();
+    std::fs::write(root.join("usr/lib/libfoo-1.0.so"), "").unwrap();
+    std::os::unix::fs::symlink("/usr/lib/libfoo-1.0.so", root.join("lib/libfoo.so.1")).unwrap();
+    std::os::unix::fs::symlink("libfoo.so.1", root.join("lib/libfoo.so")).unwrap();
+    // .. can not climb out of the sysroot
+    std::os::unix::fs::symlink("../../../../..", root.join("lib/up")).unwrap();
+    let real = std::fs::canonicalize(root.join("usr/lib/libfoo-1.0.so")).unwrap();
+
+    assert_eq!(canonicalize_in_sysroot(Some(&root), &root.join("lib/libfoo.so")).unwrap(), real);
+    assert_eq!(canonicalize_in_sysroot(Some(&root), &root.join("lib/up/usr/lib/libfoo-1.0.so")).unwrap(), real);
+    assert!(canonicalize_in_sysroot(None, &root.join("lib/libfoo.so")).is_err());
+    std::fs::remove_file(root.join("usr/lib/libfoo-1.0.so")).unwrap();
+    assert!(canonicalize_in_sysroot(Some(&root), &root.join("lib/libfoo.so")).is_err());
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::TempDir;
+use sharedpacker::filter::{glob_match, is_under_excluded_path, find_disallowed, lib_file_version, LibFilter};
+use sharedpacker::deps::DependencyNode;
+use std::collections::HashMap;
+
+#[test]
+fn glob_matches_stars_and_question_marks() {
+    assert!(glob_match("libGL*", "libGLX.so.0"));
+    assert!(glob_match("libGL*", "libGL"));
+    assert!(glob_match("*.so.?", "libm.so.6"));
+    assert!(glob_match("lib*ssl*.so*", "libssl.so.3"));
+    assert!(!glob_match("libGL*", "libEGL.so.1"));
+    assert!(!glob_match("*.so.?", "libm.so.10"));
+    assert!(!glob_match("libc.so.6", "libc.so.60"));
+}
+
+#[test]
+fn matches_paths_under_an_excluded_directory() {
+    let tmp = TempDir::new("filter-path");
+    let vendor = tmp.path.join("vendor");
+    std::fs::create_dir_all(vendor.join("lib")).unwrap();
+    std::fs::write(vendor.join("lib/libv.so"), "").unwrap();
+    std::os::unix::fs::symlink(&vendor, tmp.path.join("linked")).unwrap();
+
+    assert!(is_under_excluded_path(&vendor.join("lib/libv.so"), std::slice::from_ref(&vendor)));
+    assert!(is_under_excluded_path(&vendor.join("lib/libv.so"), &[tmp.path.join("linked")]));
+    assert!(!is_under_excluded_path(&vendor.join("lib/libv.so"), &[tmp.path.join("vend")]));
+    assert!(!is_under_excluded_path(&vendor.join("lib/libv.so"), &[]));
+}
+
+#[test]
+fn finds_the_names_that_no_allow_pattern_matches() {
+    let allow = vec!["libc.so.*".to_string(), "libm.so.6".to_string()];
+    let names = ["libc.so.6", "libm.so.6", "libz.so.1"];
+    assert_eq!(find_disallowed(names, &allow), ["libz.so.1"]);
+    assert!(find_disallowed(names, &[]).is_empty());
+}
+
+#[test]
+fn keeps_only_the_libs_that_the_target_inventory_lacks() {
+    let tmp = TempDir::new("filter-inventory");
+    std::fs::write(tmp.path.join("libssl.so.3.0.2"), "").unwrap();
+    std::os::unix::fs::symlink("libssl.so.3.0.2", tmp.path.join("libssl.so.3")).unwrap();
+    std::fs::write(tmp.path.join("libz.so.1"), "").unwrap();
+    assert_eq!(lib_file_version(&tmp.path.join("libssl.so.3")).as_deref(), Some("3.0.2"));
+
+    let dep = |name: &str| DependencyNode { name: name.into(), path: tmp.path.join(name), ..Default::default() };
+    let inventory = HashMap::from([
+        ("libssl.so.3".to_string(), Some("3.0.2".to_string())),
+        ("libz.so.1".to_string(), None),
+    ]);
+    let filter = LibFilter::missing_from(inventory);
+    assert!(!filter.keeps(&dep("libssl.so.3")));
+    assert!(!filter.keeps(&dep("libz.so.1")));
+    assert!(filter.keeps(&dep("libm.so.6")));
+
+    // the target has another version than the one being packed
+    let older = HashMap::from([("libssl.so.3".to_string(), Some("3.0.1".to_string()))]);
+    assert!(LibFilter::missing_from(older).keeps(&dep("libssl.so.3")));
+}
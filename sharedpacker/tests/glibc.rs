@@ -0,0 +1,18 @@
+mod common;
+
+use common::TempDir;
+use sharedpacker::glibc::{find_nss_libs, find_gconv_dir};
+
+#[test]
+fn finds_the_versioned_nss_modules_and_the_gconv_dir() {
+    let tmp = TempDir::new("glibc-extras");
+    for name in ["libc.so.6", "libnss_dns.so.2", "libnss_files.so.2", "libnss_files.so", "libnsl.so.1"] {
+        std::fs::write(tmp.path.join(name), "").unwrap();
+    }
+    assert_eq!(find_nss_libs(&tmp.path).unwrap(), [tmp.path.join("libnss_dns.so.2"), tmp.path.join("libnss_files.so.2")]);
+
+    assert_eq!(find_gconv_dir(&tmp.path), None);
+    std::fs::create_dir(tmp.path.join("gconv")).unwrap();
+    std::fs::write(tmp.path.join("gconv").join("gconv-modules"), "").unwrap();
+    assert_eq!(find_gconv_dir(&tmp.path), Some(tmp.path.join("gconv")));
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::TempDir;
+use sharedpacker::install::{InstallLock, install_archive};
+use std::time::Duration;
+
+#[test]
+fn replaces_the_previous_install() {
+    let tmp = TempDir::new("install-replace");
+    let archive = tmp.path.join("out");
+    std::fs::create_dir(&archive).unwrap();
+    std::fs::write(archive.join("new"), "").unwrap();
+    let install = tmp.path.join("prefix").join("app");
+    std::fs::create_dir_all(&install).unwrap();
+    std::fs::write(install.join("old"), "").unwrap();
+
+    install_archive(&archive, &install, Duration::from_secs(5)).unwrap();
+    assert!(install.join("new").is_file());
+    assert!(!install.join("old").exists(), "the previous install was not replaced");
+    // only the install itself is left behind, no staging dir or lock
+    assert_eq!(std::fs::read_dir(tmp.path.join("prefix")).unwrap().count(), 1);
+}
+
+#[test]
+fn waits_for_the_lock_of_another_install() {
+    let tmp = TempDir::new("install-lock");
+    let archive = tmp.path.join("out");
+    std::fs::create_dir(&archive).unwrap();
+    let install = tmp.path.join("app");
+
+    let lock = InstallLock::acquire(&install, Duration::from_secs(5)).unwrap();
+    assert!(install_archive(&archive, &install, Duration::from_millis(300)).is_err());
+    assert!(!install.exists());
+    drop(lock);
+    install_archive(&archive, &install, Duration::from_millis(300)).unwrap();
+    assert!(install.is_dir());
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::*;
+use sharedpacker::ldd::{parse_ldd_lines, parse_ldd_loader_line, get_loader};
+use sharedpacker::tools::ToolConfig;
+
+const LDD_WITH_WARNING: &str = "\
+ldd: warning: you do not have execution permission for `./prog'
+\tldd: warning: ignoring libfake.so => /nowhere/libfake.so from LD_PRELOAD
+\tlinux-vdso.so.1 (0x00007ffc7a1e5000)
+\tlibc.so.6 => /lib/x86_64-linux-gnu/libc.so.6 (0x00007f3c1a000000)
+\t/lib64/ld-linux-x86-64.so.2 (0x00007f3c1a3f0000)
+";
+
+#[test]
+fn skips_ldd_warning_lines() {
+    let libs = parse_ldd_lines(LDD_WITH_WARNING).unwrap();
+    let names: Vec<&str> = libs.iter().map(|l| l.name.as_str()).collect();
+    assert_eq!(names, ["libc.so.6"]);
+    assert_eq!(libs[0].path.to_str().unwrap(), "/lib/x86_64-linux-gnu/libc.so.6");
+}
+
+#[test]
+fn finds_loader_after_ldd_warning_lines() {
+    let loader = parse_ldd_loader_line(LDD_WITH_WARNING).unwrap().unwrap();
+    assert_eq!(loader.name, "ld-linux-x86-64.so.2");
+    assert_eq!(loader.path.to_str().unwrap(), "/lib64/ld-linux-x86-64.so.2");
+}
+
+const LDD_WITH_LINUX_GATE: &str = "\
+\tlinux-gate.so.1 =>  (0xb7f6c000)
+\tlinux-vdso.so.1 => (0x00007ffc7a1e5000)
+\tlibm.so.6 => /lib/i386-linux-gnu/libm.so.6 (0xb7f20000)
+\tlibc.so.6 => /lib/i386-linux-gnu/libc.so.6 (0xb7d70000)
+\t/lib/ld-linux.so.2 (0xb7f6d000)
+";
+
+#[test]
+fn skips_virtual_objects_with_an_arrow_but_no_path() {
+    let libs = parse_ldd_lines(LDD_WITH_LINUX_GATE).unwrap();
+    let names: Vec<&str> = libs.iter().map(|l| l.name.as_str()).collect();
+    assert_eq!(names, ["libm.so.6", "libc.so.6"]);
+}
+
+const LDD_WITH_ABSOLUTE_NEEDED: &str = "\
+\tlinux-vdso.so.1 (0x00007f2db99d8000)
+\t/opt/foo/libbar.so (0x00007f2db99c6000)
+\tlibc.so.6 => /lib/x86_64-linux-gnu/libc.so.6 (0x00007f2db97dc000)
+\t/opt/foo/libbaz.so => /opt/foo/libbaz.so (0x00007f2db97d0000)
+\t/lib64/ld-linux-x86-64.so.2 (0x00007f2db99da000)
+";
+
+#[test]
+fn keeps_absolute_path_needed_libs_under_their_basename() {
+    let libs = parse_ldd_lines(LDD_WITH_ABSOLUTE_NEEDED).unwrap();
+    let found: Vec<(&str, &str)> = libs.iter().map(|l| (l.name.as_str(), l.path.to_str().unwrap())).collect();
+    assert_eq!(found, [
+        ("libc.so.6", "/lib/x86_64-linux-gnu/libc.so.6"),
+        ("libbar.so", "/opt/foo/libbar.so"),
+        ("libbaz.so", "/opt/foo/libbaz.so"),
+    ]);
+}
+
+#[test]
+fn does_not_mistake_an_absolute_path_needed_lib_for_the_loader() {
+    let loader = parse_ldd_loader_line(LDD_WITH_ABSOLUTE_NEEDED).unwrap().unwrap();
+    assert_eq!(loader.name, "ld-linux-x86-64.so.2");
+}
+
+const LDD_WITH_MISSING_LIB: &str = "\
+\tlinux-vdso.so.1 (0x00007ffc7a1e5000)
+\tlibgone.so.1 => not found
+\tlibc.so.6 => /lib/x86_64-linux-gnu/libc.so.6 (0x00007f3c1a000000)
+\t/lib64/ld-linux-x86-64.so.2 (0x00007f3c1a3f0000)
+";
+
+#[test]
+fn finds_the_loader_even_when_a_lib_is_not_found() {
+    assert!(parse_ldd_lines(LDD_WITH_MISSING_LIB).is_err());
+    let loader = parse_ldd_loader_line(LDD_WITH_MISSING_LIB).unwrap().unwrap();
+    assert_eq!(loader.name, "ld-linux-x86-64.so.2");
+}
+
+#[test]
+fn finds_an_interpreter_with_a_nonstandard_name_and_path() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("custom-interp");
+    let tools = ToolConfig::default();
+    let system_loader = get_loader(&tools, &build_plain(&tmp.path, "plain")).unwrap();
+    // a loader under a name and in a place that nothing would guess
+    let custom_dir = tmp.path.join("toolchain").join("runtime");
+    std::fs::create_dir_all(&custom_dir).unwrap();
+    let custom = custom_dir.join("my-runtime-loader");
+    std::fs::copy(&system_loader.path, &custom).unwrap();
+    let exe = build_with_interpreter(&tmp.path, "custom", &custom);
+
+    let loader = get_loader(&tools, &exe).unwrap();
+    assert_eq!(loader.name, "my-runtime-loader");
+    assert_eq!(loader.path, custom);
+}
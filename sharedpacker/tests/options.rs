@@ -0,0 +1,34 @@
+use serde::{Serialize, Deserialize};
+use sharedpacker::options::overlay_json_options;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Opts {
+    timeout: u64,
+    verbose: bool,
+    output: Option<String>,
+    exclude: Vec<String>,
+}
+
+fn defaults() -> Opts {
+    Opts { timeout: 60, verbose: false, output: None, exclude: vec![] }
+}
+
+#[test]
+fn given_fields_override_the_defaults_and_the_rest_are_kept() {
+    let opts = overlay_json_options(&defaults(), r#"{"verbose": true, "exclude": ["libGL*"]}"#).unwrap();
+    assert_eq!(opts, Opts { timeout: 60, verbose: true, output: None, exclude: vec!["libGL*".into()] });
+}
+
+#[test]
+fn unknown_fields_are_rejected_with_the_known_ones() {
+    let err = overlay_json_options(&defaults(), r#"{"verbose": true, "make_wraper": true}"#).unwrap_err().to_string();
+    assert!(err.contains("make_wraper") && err.contains("exclude, output, timeout, verbose"), "{}", err);
+}
+
+#[test]
+fn wrongly_typed_fields_and_non_objects_are_rejected() {
+    assert!(overlay_json_options(&defaults(), r#"{"timeout": "soon"}"#).is_err());
+    assert!(overlay_json_options(&defaults(), "[]").is_err());
+    assert!(overlay_json_options(&defaults(), "{").is_err());
+}
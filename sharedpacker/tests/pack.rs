@@ -0,0 +1,1240 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::*;
+use sharedpacker::elf::read_interpreter;
+use std::process::Command;
+
+#[test]
+fn packs_sample_with_its_libs_and_loader() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("pack");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap()]);
+    assert_success(&out);
+
+    for name in ["sample", "libone.so", "libtwo.so", "libc.so.6"] {
+        assert!(output.join(name).is_file(), "{} is missing from the bundle", name);
+    }
+    let has_loader = std::fs::read_dir(&output).unwrap()
+        .any(|e| e.unwrap().file_name().to_string_lossy().starts_with("ld-"));
+    assert!(has_loader, "the loader is missing from the bundle");
+
+    // the patched interpreter is relative, so run it from inside the bundle
+    let run = Command::new("./sample").current_dir(&output).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn wrapper_runs_sample_from_anywhere() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("wrapper");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--make-wrapper"]);
+    assert_success(&out);
+    assert!(output.join(".sample-original").is_file());
+
+    let run = Command::new(output.join("sample")).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn bundles_a_lib_that_is_needed_by_its_absolute_path() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("absolute-needed");
+    let exe = build_absolute_needed(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[exe.to_str().unwrap(), "-o", output.to_str().unwrap(), "--make-wrapper"]);
+    assert_success(&out);
+    assert!(output.join("libabs.so").is_file(), "libabs.so is missing from the bundle");
+
+    // the bundled copy must be the one that gets loaded
+    std::fs::remove_file(tmp.path.join("libabs.so")).unwrap();
+    let run = Command::new(output.join("absneeded")).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "7\n");
+}
+
+#[test]
+fn name_renames_the_executable_and_its_wrapper() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("name");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--make-wrapper", "--name", "renamed"]);
+    assert_success(&out);
+    assert!(output.join(".renamed-original").is_file());
+    assert!(!output.join("sample").exists());
+
+    let run = Command::new(output.join("renamed")).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn merge_adds_to_an_existing_bundle() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("merge");
+    let sample = build_sample(&tmp.path);
+    let plain = build_plain(&tmp.path, "plain");
+    let output = tmp.path.join("out");
+    let output_str = output.to_str().unwrap();
+
+    assert_success(&sharedpacker(&[sample.to_str().unwrap(), "-o", output_str, "--merge"]));
+    let out = sharedpacker(&[plain.to_str().unwrap(), "-o", output_str, "--merge"]);
+    assert_success(&out);
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("Kept the existing"),
+        "unexpected collisions:\n{}", String::from_utf8_lossy(&out.stderr));
+
+    for name in ["sample", "plain", "libone.so", "libtwo.so"] {
+        assert!(output.join(name).is_file(), "{} is missing from the bundle", name);
+    }
+    let run = Command::new("./sample").current_dir(&output).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn merge_reports_a_differing_file_instead_of_overwriting_it() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("merge-collision");
+    let first_dir = tmp.path.join("first");
+    let second_dir = tmp.path.join("second");
+    std::fs::create_dir_all(&first_dir).unwrap();
+    std::fs::create_dir_all(&second_dir).unwrap();
+    let first = build_plain(&first_dir, "prog");
+    let second = build_sample(&second_dir);
+    let second_prog = second_dir.join("prog");
+    std::fs::rename(&second, &second_prog).unwrap();
+    let output = tmp.path.join("out");
+    let output_str = output.to_str().unwrap();
+
+    assert_success(&sharedpacker(&[first.to_str().unwrap(), "-o", output_str, "--merge"]));
+    let out = sharedpacker(&[second_prog.to_str().unwrap(), "-o", output_str, "--merge"]);
+    assert_success(&out);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Kept the existing") && stderr.contains("prog"), "{}", stderr);
+
+    let run = Command::new("./prog").current_dir(&output).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "prog\n");
+}
+
+#[test]
+fn merge_into_a_bundle_packed_without_merge_skips_identical_files() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("merge-no-state");
+    let plain = build_plain(&tmp.path, "plain");
+    let other = build_plain(&tmp.path, "other");
+    let output = tmp.path.join("out");
+    let output_str = output.to_str().unwrap();
+    let loader = read_interpreter(&plain).unwrap().unwrap();
+    let loader_name = loader.rsplit('/').next().unwrap();
+
+    assert_success(&sharedpacker(&[plain.to_str().unwrap(), "-o", output_str]));
+    assert!(!output.join(".sharedpacker-state").exists());
+    let out = sharedpacker(&[other.to_str().unwrap(), "-o", output_str, "--merge"]);
+    assert_success(&out);
+    // the loader is copied as is, so the one already there is the same file. libc was patched, and differs
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!stderr.contains(&format!("  {} (from", loader_name)), "{}", stderr);
+    assert!(stderr.contains("  libc.so.6 (from"), "{}", stderr);
+
+    let run = Command::new("./other").current_dir(&output).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "other\n");
+}
+
+#[test]
+fn self_extracting_script_runs_sample_and_cleans_up() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("self-extract");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+    let script = tmp.path.join("sample.run");
+    let extract_root = tmp.path.join("extract-root");
+    std::fs::create_dir(&extract_root).unwrap();
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(),
+        "--self-extract", script.to_str().unwrap()]);
+    assert_success(&out);
+
+    let run = Command::new(&script).env("TMPDIR", &extract_root).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+    assert_eq!(std::fs::read_dir(&extract_root).unwrap().count(), 0, "the temp dir was not removed");
+}
+
+#[test]
+fn jobs_file_packs_every_job_and_reports_the_failed_one() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("jobs");
+    let sample = build_sample(&tmp.path);
+    let plain = build_plain(&tmp.path, "plain");
+    let jobs = tmp.path.join("jobs.json");
+    std::fs::write(&jobs, format!(r#"[
+        {{ "exe": "{}", "output": "{}", "exclude": ["libone*"] }},
+        {{ "exe": "{}", "output": "{}" }},
+        {{ "exe": "{}", "output": "{}", "make_wrapper": true }}
+    ]"#,
+        sample.display(), tmp.path.join("out-sample").display(),
+        tmp.path.join("does-not-exist").display(), tmp.path.join("out-missing").display(),
+        plain.display(), tmp.path.join("out-plain").display())).unwrap();
+
+    let out = sharedpacker(&["--jobs-file", jobs.to_str().unwrap()]);
+    assert!(!out.status.success(), "a failed job should make the run fail");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Packed 2 of 3 jobs"), "unexpected summary:\n{}", stderr);
+
+    let sample_out = tmp.path.join("out-sample");
+    assert!(sample_out.join("libtwo.so").is_file());
+    assert!(!sample_out.join("libone.so").exists(), "libone.so should have been excluded");
+    assert!(tmp.path.join("out-plain").join(".plain-original").is_file());
+}
+
+#[test]
+fn deps_lists_the_libs_without_copying_anything() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("deps");
+    let sample = build_sample(&tmp.path);
+
+    let out = Command::new(env!("CARGO_BIN_EXE_sharedpacker"))
+        .args(["deps", sample.to_str().unwrap()])
+        .current_dir(&tmp.path)
+        .output().unwrap();
+    assert_success(&out);
+
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    for name in ["libone.so", "libtwo.so", "libc.so.6"] {
+        assert!(stdout.lines().any(|l| l.starts_with(&format!("{} => ", name))), "{} is not listed in:\n{}", name, stdout);
+    }
+    assert!(stdout.lines().last().unwrap().starts_with("ld-"), "the loader is not listed last in:\n{}", stdout);
+    assert!(!tmp.path.join("sharedpacker_out").exists());
+}
+
+#[test]
+fn verify_patch_accepts_a_correctly_patched_bundle() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("verify-patch");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--verify-patch"]);
+    assert_success(&out);
+    let run = Command::new("./sample").current_dir(&output).output().unwrap();
+    assert_success(&run);
+}
+
+#[test]
+fn extra_lib_is_bundled_and_patched_like_a_needed_lib() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("extra-lib");
+    let sample = build_plain(&tmp.path, "plain");
+    // libtwo.so needs libone.so, and plain needs neither
+    build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[
+        sample.to_str().unwrap(), "-o", output.to_str().unwrap(),
+        "--extra-lib", tmp.path.join("libtwo.so").to_str().unwrap(),
+    ]);
+    assert_success(&out);
+    assert!(output.join("libtwo.so").is_file());
+    assert!(output.join("libone.so").is_file(), "the extra lib's own needed lib is missing");
+}
+
+#[test]
+fn temp_then_move_patches_outside_the_output() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("patch-strategy");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--patch-strategy", "temp-then-move"]);
+    assert_success(&out);
+    let run = Command::new("./sample").current_dir(&output).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+    // the scratch directory is gone once packing is done
+    let leftovers: Vec<_> = std::fs::read_dir(&tmp.path).unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|n| n.contains("patching"))
+        .collect();
+    assert!(leftovers.is_empty(), "scratch directories were left behind: {:?}", leftovers);
+}
+
+#[test]
+fn wrapper_runs_from_an_output_with_a_space_in_its_path() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("space");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("my bundle");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--make-wrapper"]);
+    assert_success(&out);
+
+    let run = Command::new(output.join("sample")).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn no_wrapper_rename_puts_the_wrapper_next_to_the_executable() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("no-wrapper-rename");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--make-wrapper", "--no-wrapper-rename"]);
+    assert_success(&out);
+    assert!(!output.join(".sample-original").exists());
+    assert!(std::fs::read(output.join("sample")).unwrap().starts_with(b"\x7fELF"), "the executable was replaced");
+
+    let run = Command::new(output.join("sample.sh")).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn copies_a_symlinked_loader_under_the_name_the_executable_uses() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("symlinked-loader");
+    let plain = build_plain(&tmp.path, "plain");
+    let system_loader = Command::new("patchelf").args(["--print-interpreter", plain.to_str().unwrap()])
+        .output().unwrap().stdout;
+    let system_loader = String::from_utf8_lossy(&system_loader).trim().trim_end_matches('\0').to_string();
+    let link = tmp.path.join("ld-link.so.2");
+    std::os::unix::fs::symlink(&system_loader, &link).unwrap();
+    let exe = build_with_interpreter(&tmp.path, "linked", &link);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[exe.to_str().unwrap(), "-o", output.to_str().unwrap()]);
+    assert_success(&out);
+
+    let copied = output.join("ld-link.so.2");
+    assert!(copied.symlink_metadata().unwrap().file_type().is_file(), "the loader was not copied as a real file");
+    let interpreter = Command::new("patchelf").args(["--print-interpreter", output.join("linked").to_str().unwrap()])
+        .output().unwrap().stdout;
+    assert_eq!(String::from_utf8_lossy(&interpreter).trim().trim_end_matches('\0'), "./ld-link.so.2");
+    let run = Command::new("./linked").current_dir(&output).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "linked\n");
+}
+
+#[test]
+fn map_replaces_the_lib_that_ldd_finds() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("map");
+    let sample = build_sample(&tmp.path);
+    let alt_dir = tmp.path.join("alt");
+    std::fs::create_dir(&alt_dir).unwrap();
+    std::fs::write(alt_dir.join("one.c"), "int one(void) { return 5; }\n").unwrap();
+    cc(&["-shared", "-fPIC", "-Wl,-soname,libone.so", "-o", "libone.so", "one.c"], &alt_dir);
+    let output = tmp.path.join("out");
+
+    let map = format!("libone.so={}", alt_dir.join("libone.so").display());
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--map", &map]);
+    assert_success(&out);
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("Warning"), "the matching SONAME was warned about");
+
+    let run = Command::new("./sample").current_dir(&output).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "6\n");
+}
+
+#[test]
+fn check_passes_a_fresh_bundle_and_reports_a_missing_lib() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("check");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+    assert_success(&sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--make-wrapper"]));
+
+    let check = sharedpacker(&["check", output.to_str().unwrap()]);
+    assert_success(&check);
+
+    std::fs::remove_file(output.join("libone.so")).unwrap();
+    let check = sharedpacker(&["check", output.to_str().unwrap()]);
+    assert!(!check.status.success());
+    let stdout = String::from_utf8_lossy(&check.stdout);
+    assert!(stdout.contains("libtwo.so: needs libone.so, which is not in the bundle"), "{}", stdout);
+}
+
+#[test]
+fn check_passes_mirror_path_and_base_rpath_bundles() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("check-layouts");
+    let sample = build_sample(&tmp.path);
+    let layouts: [(&str, &[&str]); 3] = [
+        ("mirror", &["--mirror-paths"]),
+        ("base-rpath", &["--base-rpath", "/opt/base/lib"]),
+        ("append-base-rpath", &["--append-rpath", "--base-rpath", "/opt/base/lib"]),
+    ];
+    for (name, flags) in layouts {
+        let output = tmp.path.join(name);
+        let mut args = vec![sample.to_str().unwrap(), "-o", output.to_str().unwrap()];
+        args.extend(flags);
+        assert_success(&sharedpacker(&args));
+        let check = sharedpacker(&["check", output.to_str().unwrap()]);
+        assert!(check.status.success(), "{}:\n{}", name, String::from_utf8_lossy(&check.stdout));
+    }
+
+    // a lib missing from its mirrored directory is still caught
+    let output = tmp.path.join("mirror");
+    std::fs::remove_file(output.join(tmp.path.strip_prefix("/").unwrap()).join("libone.so")).unwrap();
+    let check = sharedpacker(&["check", output.to_str().unwrap()]);
+    assert!(!check.status.success());
+    let stdout = String::from_utf8_lossy(&check.stdout);
+    assert!(stdout.contains("libtwo.so: needs libone.so, which is not in the bundle"), "{}", stdout);
+}
+
+#[test]
+fn a_missing_lib_says_what_needs_it_and_where_was_searched() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("not-found");
+    let sample = build_sample(&tmp.path);
+    std::fs::remove_file(tmp.path.join("libone.so")).unwrap();
+
+    let out = sharedpacker(&["deps", sample.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("libone.so is not found"), "{}", stderr);
+    assert!(stderr.contains("while resolving the libs of: sample"), "{}", stderr);
+    assert!(stderr.contains(&format!("the rpath of sample (\"{}\")", tmp.path.display())), "{}", stderr);
+    assert!(stderr.contains("--sysroot"), "{}", stderr);
+}
+
+#[test]
+fn native_elf_reads_the_rpath_without_patchelf() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("native-rpath");
+    let sample = build_sample(&tmp.path);
+    std::fs::remove_file(tmp.path.join("libone.so")).unwrap();
+    // only ldd is on the PATH
+    let bin = tmp.path.join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    let ldd = String::from_utf8(Command::new("sh").args(["-c", "command -v ldd"]).output().unwrap().stdout).unwrap();
+    std::os::unix::fs::symlink(ldd.trim(), bin.join("ldd")).unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_sharedpacker"))
+        .args(["deps", "--native-elf", sample.to_str().unwrap()])
+        .env("PATH", &bin)
+        .output().unwrap();
+    assert_eq!(out.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains(&format!("the rpath of sample (\"{}\")", tmp.path.display())), "{}", stderr);
+}
+
+#[test]
+fn mirror_paths_keeps_each_lib_under_its_source_directory() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("mirror-paths");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--mirror-paths"]);
+    assert_success(&out);
+    let lib_dir = std::fs::canonicalize(&tmp.path).unwrap();
+    let mirrored = output.join(lib_dir.strip_prefix("/").unwrap());
+    assert!(mirrored.join("libone.so").is_file(), "libone.so is not under {:?}", mirrored);
+    assert!(!output.join("libone.so").exists());
+
+    let run = Command::new("./sample").current_dir(&output).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn dry_run_prints_the_plan_without_creating_the_output() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("dry-run");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--dry-run"]);
+    assert_success(&out);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("libone.so <= "), "{}", stdout);
+    assert!(stdout.contains("(loader)"), "{}", stdout);
+    assert!(!output.exists());
+}
+
+#[test]
+fn generated_installer_links_the_wrapper_and_refuses_to_clobber() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("installer");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+    let prefix = tmp.path.join("prefix");
+
+    assert_success(&sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--gen-installer"]));
+    let install = || Command::new(output.join("install.sh")).arg(&prefix).output().unwrap();
+    assert_success(&install());
+    let run = Command::new(prefix.join("bin/sample")).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+
+    // the same bundle again is fine, but a changed install is not replaced without --force
+    assert_success(&install());
+    std::fs::write(prefix.join("lib/out/extra"), "").unwrap();
+    assert!(!install().status.success());
+    let forced = Command::new(output.join("install.sh")).arg("--force").arg(&prefix).output().unwrap();
+    assert_success(&forced);
+    assert!(!prefix.join("lib/out/extra").exists());
+}
+
+#[test]
+fn an_already_patched_lib_is_left_as_is() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("already-patched");
+    let sample = build_sample(&tmp.path);
+    let plain = build_plain(&tmp.path, "plain");
+    let first = tmp.path.join("first");
+    assert_success(&sharedpacker(&[sample.to_str().unwrap(), "-o", first.to_str().unwrap()]));
+
+    let second = tmp.path.join("second");
+    let out = sharedpacker(&[
+        plain.to_str().unwrap(), "-o", second.to_str().unwrap(), "--quiet", "--verbose",
+        "--extra-lib", first.join("libone.so").to_str().unwrap(),
+    ]);
+    assert_success(&out);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("libone.so is already patched"), "{}", stderr);
+    assert!(!stderr.contains("plain is already patched"), "{}", stderr);
+}
+
+#[test]
+fn output_dash_streams_the_bundle_as_a_tarball() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("stdout");
+    let sample = build_sample(&tmp.path);
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", "-", "--make-wrapper"]);
+    assert_success(&out);
+    let tarball = tmp.path.join("bundle.tar");
+    std::fs::write(&tarball, &out.stdout).unwrap();
+    let extract = Command::new("tar").arg("-xf").arg(&tarball).current_dir(&tmp.path).output().unwrap();
+    assert_success(&extract);
+
+    let run = Command::new(tmp.path.join("sharedpacker_out/sample")).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn base_manifest_leaves_out_the_libs_the_base_has() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("base-manifest");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+    let manifest = tmp.path.join("SHA256SUMS");
+    let sums = Command::new("sha256sum").arg(tmp.path.join("libone.so")).output().unwrap();
+    std::fs::write(&manifest, &sums.stdout).unwrap();
+
+    let out = sharedpacker(&[
+        sample.to_str().unwrap(), "-o", output.to_str().unwrap(),
+        "--base-manifest", manifest.to_str().unwrap(), "--base-rpath", tmp.path.to_str().unwrap(),
+    ]);
+    assert_success(&out);
+    assert!(!output.join("libone.so").exists(), "a lib that the base has was bundled");
+    assert!(output.join("libtwo.so").is_file());
+    // libone.so is found through the base rpath instead
+    let run = Command::new("./sample").current_dir(&output).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn target_inventory_leaves_out_the_libs_the_target_has() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("target-inventory");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+    let inventory = tmp.path.join("inventory");
+    std::fs::write(&inventory, "# what the target has\nlibone.so\n").unwrap();
+
+    let out = sharedpacker(&[
+        sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--target-inventory", inventory.to_str().unwrap(),
+    ]);
+    assert_success(&out);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Left out these libs, which the target already has: libone.so"), "{}", stderr);
+    assert!(!output.join("libone.so").exists(), "a lib that the target has was bundled");
+    assert!(output.join("libtwo.so").is_file());
+    // libone.so comes from the system path, the bundled libtwo.so from $ORIGIN
+    let run = Command::new("./sample").current_dir(&output).env("LD_LIBRARY_PATH", &tmp.path).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn max_size_stops_copying_once_the_bundle_is_over_it() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("max-size");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--max-size", "1", "--keep-on-error"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("over the --max-size of 1 bytes"), "{}", stderr);
+    // the first file took it over the limit, so nothing after it was copied
+    let copied: Vec<_> = std::fs::read_dir(&output).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(copied.len(), 1, "{:?}", copied);
+}
+
+#[test]
+fn best_effort_still_prints_the_summary_when_a_file_fails() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("best-effort-summary");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+    // a directory where libone.so is copied to makes its copy fail
+    std::fs::create_dir_all(output.join("libone.so")).unwrap();
+
+    let out = sharedpacker(&[
+        sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--incremental", "--best-effort", "--summary-json",
+    ]);
+    assert!(!out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let summary: serde_json::Value = serde_json::from_str(stdout.trim()).expect("no summary was printed");
+    assert_eq!(summary["failed"], 1);
+}
+
+#[test]
+fn a_lib_needed_under_two_names_is_copied_once_and_symlinked() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("alias");
+    std::fs::write(tmp.path.join("one.c"), "int one(void) { return 1; }\n").unwrap();
+    std::fs::write(tmp.path.join("two.c"), "int one(void);\nint two(void) { return one() + 1; }\n").unwrap();
+    std::fs::write(tmp.path.join("sample.c"),
+        "#include <stdio.h>\nint two(void);\nint main(void) { printf(\"%d\\n\", two()); return 0; }\n").unwrap();
+    let rpath = format!("-Wl,-rpath,{}", tmp.path.display());
+    cc(&["-shared", "-fPIC", "-o", "libone.so.1", "one.c"], &tmp.path);
+    std::os::unix::fs::symlink("libone.so.1", tmp.path.join("libone.so")).unwrap();
+    // libtwo.so needs libone.so, and sample needs libone.so.1, which is the same file
+    cc(&["-shared", "-fPIC", "-o", "libtwo.so", "two.c", "-L.", "-l:libone.so", &rpath], &tmp.path);
+    cc(&["-o", "sample", "sample.c", "-L.", "-Wl,--no-as-needed", "-ltwo", "-l:libone.so.1", &rpath], &tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[tmp.path.join("sample").to_str().unwrap(), "-o", output.to_str().unwrap()]);
+    assert_success(&out);
+    let names = ["libone.so", "libone.so.1"];
+    let links: Vec<bool> = names.iter().map(|n| output.join(n).symlink_metadata().unwrap().file_type().is_symlink()).collect();
+    assert_eq!(links.iter().filter(|l| **l).count(), 1, "expected one copy and one symlink of {:?}", names);
+    let run = Command::new("./sample").current_dir(&output).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn refuses_an_executable_named_like_one_of_its_libs() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("name-collision");
+    let sample = build_sample(&tmp.path);
+    let renamed_dir = tmp.path.join("renamed");
+    std::fs::create_dir(&renamed_dir).unwrap();
+    let renamed = renamed_dir.join("libone.so");
+    std::fs::copy(&sample, &renamed).unwrap();
+
+    let out = sharedpacker(&[renamed.to_str().unwrap(), "-o", tmp.path.join("out").to_str().unwrap()]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("would be copied to"), "{}", stderr);
+}
+
+#[test]
+fn excluded_and_target_libs_are_both_left_out_by_the_copy() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("exclude-and-target");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+    let inventory = tmp.path.join("inventory");
+    std::fs::write(&inventory, "libtwo.so\n").unwrap();
+    let report = tmp.path.join("report.json");
+
+    let out = sharedpacker(&[
+        sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--exclude", "libone*",
+        "--target-inventory", inventory.to_str().unwrap(), "--report", report.to_str().unwrap(), "--summary-json",
+    ]);
+    assert_success(&out);
+    assert!(!output.join("libone.so").exists() && !output.join("libtwo.so").exists());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("which the target already has: libtwo.so\n"), "{}", stderr);
+
+    let records: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    let status = |name: &str| records.as_array().unwrap().iter()
+        .find(|r| r["name"] == name).map(|r| r["status"].clone()).unwrap();
+    assert_eq!(status("libone.so"), "excluded");
+    assert_eq!(status("libtwo.so"), "on-target");
+    let summary: serde_json::Value = serde_json::from_str(String::from_utf8_lossy(&out.stdout).trim()).unwrap();
+    assert_eq!(summary["excluded"], 1);
+    assert_eq!(summary["on_target"], 1);
+    assert_eq!(summary["deduplicated"], 0);
+}
+
+#[test]
+fn parallel_copy_and_patch_makes_a_working_bundle() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("parallel");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[
+        sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--copy-jobs", "4", "--patch-jobs", "2",
+    ]);
+    assert_success(&out);
+    let run = Command::new("./sample").current_dir(&output).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn a_symlinked_executable_is_bundled_under_its_real_name() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("symlink-exe");
+    build_sample(&tmp.path);
+    let link = tmp.path.join("link");
+    std::os::unix::fs::symlink("sample", &link).unwrap();
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[link.to_str().unwrap(), "-o", output.to_str().unwrap()]);
+    assert_success(&out);
+    assert!(output.join("sample").is_file());
+    assert!(!output.join("link").exists());
+}
+
+#[test]
+fn keep_symlink_name_bundles_a_symlinked_executable_under_the_link_name() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("keep-symlink-name");
+    build_sample(&tmp.path);
+    let link = tmp.path.join("link");
+    std::os::unix::fs::symlink("sample", &link).unwrap();
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[link.to_str().unwrap(), "-o", output.to_str().unwrap(), "--keep-symlink-name"]);
+    assert_success(&out);
+    let bundled = output.join("link");
+    assert!(bundled.symlink_metadata().unwrap().file_type().is_file(), "the link itself was copied instead of its target");
+    assert!(!output.join("sample").exists());
+    let run = Command::new("./link").current_dir(&output).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn progress_json_reports_each_step_as_a_json_line() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("progress-json");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--progress-json"]);
+    assert_success(&out);
+    let events: Vec<(String, Option<String>)> = String::from_utf8_lossy(&out.stderr).lines()
+        .filter(|l| l.starts_with('{'))
+        .map(|l| {
+            let value: serde_json::Value = serde_json::from_str(l).unwrap();
+            (value["event"].as_str().unwrap().to_string(), value["name"].as_str().map(|n| n.to_string()))
+        })
+        .collect();
+    assert_eq!(events.first().map(|e| e.0.as_str()), Some("resolve_started"));
+    assert_eq!(events.last().map(|e| e.0.as_str()), Some("done"));
+    for event in ["resolved", "copied", "patched"] {
+        assert!(events.contains(&(event.to_string(), Some("libone.so".to_string()))), "no {} event for libone.so in {:?}", event, events);
+    }
+}
+
+#[test]
+fn wrapper_only_loader_leaves_the_interpreter_alone() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("wrapper-only-loader");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--wrapper-only-loader"]);
+    assert_success(&out);
+    let original = read_interpreter(&sample).unwrap();
+    assert_eq!(read_interpreter(&output.join(".sample-original")).unwrap(), original);
+    assert!(std::fs::read_dir(&output).unwrap()
+        .any(|e| e.unwrap().file_name().to_string_lossy().starts_with("ld-")), "the loader was not copied");
+    let run = Command::new(output.join("sample")).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn reproducible_runs_make_identical_tarballs() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("reproducible");
+    let sample = build_sample(&tmp.path);
+    let mut tarballs = vec![];
+    for run in ["a", "b"] {
+        let output = tmp.path.join(run).join("out");
+        let tarball = tmp.path.join(format!("{}.tar.gz", run));
+        let out = sharedpacker(&[
+            sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--make-wrapper",
+            "--reproducible", "--targz", tarball.to_str().unwrap(),
+        ]);
+        assert_success(&out);
+        tarballs.push(std::fs::read(&tarball).unwrap());
+        // so that the second run is at a different time
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+    }
+    assert!(tarballs[0] == tarballs[1], "the two tarballs differ");
+}
+
+#[test]
+fn absolute_interp_runs_directly_from_another_directory() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("absolute-interp");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    // the output is installed where it is built
+    let out = sharedpacker(&[
+        sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--absolute-interp", output.to_str().unwrap(),
+    ]);
+    assert_success(&out);
+    let run = Command::new(output.join("sample")).current_dir("/").output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn verify_runnable_accepts_a_fresh_bundle() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("verify-runnable");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--make-wrapper", "--verify-runnable"]);
+    assert_success(&out);
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("cannot load"));
+}
+
+#[test]
+fn no_recurse_bundles_only_the_direct_libs() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("no-recurse");
+    build_sample(&tmp.path);
+    // needs libtwo.so directly, and libone.so only through it
+    let rpath = format!("-Wl,-rpath,{}", tmp.path.display());
+    cc(&["-o", "onlytwo", "sample.c", "-L.", "-ltwo", &rpath], &tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[tmp.path.join("onlytwo").to_str().unwrap(), "-o", output.to_str().unwrap(), "--no-recurse"]);
+    assert_success(&out);
+    assert!(output.join("libtwo.so").is_file());
+    assert!(output.join("libc.so.6").is_file());
+    assert!(!output.join("libone.so").exists(), "a lib that is only needed by another lib was bundled");
+}
+
+#[test]
+fn refuses_executables_that_need_different_loaders() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("two-loaders");
+    let plain = build_plain(&tmp.path, "plain");
+    let system_loader = Command::new("patchelf").args(["--print-interpreter", plain.to_str().unwrap()])
+        .output().unwrap();
+    let system_loader = String::from_utf8_lossy(&system_loader.stdout).trim().trim_end_matches('\0').to_string();
+    let other_loader = tmp.path.join("other-ld.so");
+    std::fs::copy(&system_loader, &other_loader).unwrap();
+    let other = build_with_interpreter(&tmp.path, "other", &other_loader);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[plain.to_str().unwrap(), other.to_str().unwrap(), "-o", output.to_str().unwrap()]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("need different loaders") && stderr.contains("other-ld.so"), "{}", stderr);
+    assert!(!output.exists());
+}
+
+#[test]
+fn prunes_a_lib_with_the_same_soname_as_another() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("prune-sonames");
+    let sample = build_sample(&tmp.path);
+    // relink sample to also need libone-copy.so, and then give both copies of libone the same soname
+    let rpath = format!("-Wl,-rpath,{}", tmp.path.display());
+    cc(&["-shared", "-fPIC", "-o", "libone-copy.so", "one.c"], &tmp.path);
+    cc(&["-o", "sample", "sample.c", "-L.", "-ltwo", "-Wl,--no-as-needed", "-lone-copy", &rpath], &tmp.path);
+    for name in ["libone.so", "libone-copy.so"] {
+        cc(&["-shared", "-fPIC", "-Wl,-soname,libone.so", "-o", name, "one.c"], &tmp.path);
+    }
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--prune-duplicate-sonames"]);
+    assert_success(&out);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Pruned libone"), "{}", stderr);
+    let bundled = ["libone.so", "libone-copy.so"].iter().filter(|name| output.join(name).exists()).count();
+    assert_eq!(bundled, 1);
+
+    let run = Command::new("./sample").current_dir(&output).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn sets_the_exec_mode_on_the_executable_and_wrapper() {
+    use std::os::unix::fs::PermissionsExt;
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("exec-mode");
+    let plain = build_plain(&tmp.path, "plain");
+    std::fs::set_permissions(&plain, std::fs::Permissions::from_mode(0o777)).unwrap();
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[plain.to_str().unwrap(), "-o", output.to_str().unwrap(), "--make-wrapper", "--exec-mode", "0750"]);
+    assert_success(&out);
+    for name in ["plain", ".plain-original"] {
+        let mode = std::fs::metadata(output.join(name)).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode, 0o750, "{} has mode {:o}", name, mode);
+    }
+    let run = Command::new(output.join("plain")).output().unwrap();
+    assert_success(&run);
+}
+
+#[test]
+fn stamps_the_output_folder_name() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("stamp");
+    let plain = build_plain(&tmp.path, "plain");
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[plain.to_str().unwrap(), "-o", output.to_str().unwrap(), "--stamp-value", "1.2.0"]);
+    assert_success(&out);
+    assert!(tmp.path.join("out-1.2.0").join("plain").is_file());
+    assert!(!output.exists());
+
+    let build_id = sharedpacker::elf::read_build_id(&plain).unwrap().expect("plain has no build-id");
+    let out = sharedpacker(&[plain.to_str().unwrap(), "-o", output.to_str().unwrap(), "--stamp", "build-id"]);
+    assert_success(&out);
+    assert!(tmp.path.join(format!("out-{}", build_id)).join("plain").is_file());
+}
+
+#[test]
+fn flattens_a_lib_whose_runpath_points_at_a_sibling_lib_dir() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("origin-runpath");
+    // tmp/bin/sample needs tmp/sub/libtwo.so, which finds tmp/lib/libone.so through $ORIGIN/../lib
+    for dir in ["bin", "sub", "lib"] {
+        std::fs::create_dir_all(tmp.path.join(dir)).unwrap();
+    }
+    std::fs::write(tmp.path.join("one.c"), "int one(void) { return 1; }\n").unwrap();
+    std::fs::write(tmp.path.join("two.c"), "int one(void);\nint two(void) { return one() + 1; }\n").unwrap();
+    std::fs::write(tmp.path.join("sample.c"),
+        "#include <stdio.h>\nint two(void);\nint main(void) { printf(\"%d\\n\", two()); return 0; }\n").unwrap();
+    cc(&["-shared", "-fPIC", "-o", "lib/libone.so", "one.c"], &tmp.path);
+    cc(&["-shared", "-fPIC", "-o", "sub/libtwo.so", "two.c", "-Llib", "-lone", "-Wl,--enable-new-dtags,-rpath,$ORIGIN/../lib"], &tmp.path);
+    let rpath = format!("-Wl,-rpath,{}", tmp.path.join("sub").display());
+    cc(&["-o", "bin/sample", "sample.c", "-Lsub", "-ltwo", &rpath], &tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[tmp.path.join("bin/sample").to_str().unwrap(), "-o", output.to_str().unwrap(), "--make-wrapper"]);
+    assert_success(&out);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("libtwo.so") && stderr.contains("$ORIGIN/../lib"), "{}", stderr);
+    assert!(output.join("libone.so").is_file() && output.join("libtwo.so").is_file());
+
+    let run = Command::new(output.join("sample")).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+    let run = Command::new("./.sample-original").current_dir(&output).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+}
+
+#[test]
+fn shows_where_the_bundled_libs_resolve_to() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("show-resolved");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+
+    let out = sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--show-resolved"]);
+    assert_success(&out);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    for name in ["libtwo.so", "libone.so", "libc.so.6"] {
+        let line = format!("\t{} => {}\n", name, output.join(name).display());
+        assert!(stderr.contains(&line), "missing {:?} in:\n{}", line, stderr);
+    }
+    assert!(!stderr.contains("not found") && !stderr.contains("outside the output"), "{}", stderr);
+}
+
+#[test]
+fn reads_every_option_from_json_on_stdin() {
+    use std::io::Write;
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("options-json");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+    let options = format!(r#"{{"exepath": [{:?}], "output": {:?}, "make_wrapper": true, "patch_strategy": "temp-then-move"}}"#,
+        sample, output);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sharedpacker"))
+        .args(["--options-json", "-"])
+        .stdin(std::process::Stdio::piped()).stdout(std::process::Stdio::piped()).stderr(std::process::Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(options.as_bytes()).unwrap();
+    assert_success(&child.wait_with_output().unwrap());
+    let run = Command::new(output.join("sample")).output().unwrap();
+    assert_success(&run);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sharedpacker"))
+        .args(["--options-json", "-"])
+        .stdin(std::process::Stdio::piped()).stderr(std::process::Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(br#"{"make_wraper": true}"#).unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Unknown options in the options JSON: make_wraper"));
+}
+
+#[test]
+fn an_output_under_a_file_fails_with_the_file_named() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("output-under-file");
+    let plain = build_plain(&tmp.path, "plain");
+    let file = tmp.path.join("not-a-dir");
+    std::fs::write(&file, "").unwrap();
+    let output = file.join("out");
+
+    for extra in [&["--dry-run"][..], &[]] {
+        let mut args = vec![plain.to_str().unwrap(), "-o", output.to_str().unwrap()];
+        args.extend(extra);
+        let out = sharedpacker(&args);
+        assert!(!out.status.success());
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains("is a file, not a directory") && stderr.contains("not-a-dir"), "{}", stderr);
+    }
+}
+
+#[test]
+fn squashfs_format_says_when_mksquashfs_is_missing() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("squashfs-missing");
+    let plain = build_plain(&tmp.path, "plain");
+    let empty_path = tmp.path.join("empty-path");
+    std::fs::create_dir(&empty_path).unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_sharedpacker"))
+        .args([plain.to_str().unwrap(), "-o", tmp.path.join("out.squashfs").to_str().unwrap(), "--format", "squashfs"])
+        .env("PATH", &empty_path)
+        .output().unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("needs mksquashfs"));
+}
+
+#[test]
+fn squashfs_format_writes_an_image_with_the_executable_bits() {
+    if !tools_available() {
+        return;
+    }
+    for tool in ["mksquashfs", "unsquashfs"] {
+        let found = Command::new("sh").args(["-c", &format!("command -v {}", tool)]).output().unwrap().status.success();
+        if !found {
+            eprintln!("skipping: {} is not available", tool);
+            return;
+        }
+    }
+    let tmp = TempDir::new("squashfs");
+    let plain = build_plain(&tmp.path, "plain");
+    let image = tmp.path.join("out.squashfs");
+
+    let out = sharedpacker(&[plain.to_str().unwrap(), "-o", image.to_str().unwrap(), "--format", "squashfs", "--make-wrapper"]);
+    assert_success(&out);
+    assert!(image.is_file());
+    let listing = Command::new("unsquashfs").args(["-ll", image.to_str().unwrap()]).output().unwrap();
+    assert_success(&listing);
+    let listing = String::from_utf8_lossy(&listing.stdout);
+    let wrapper = listing.lines().find(|l| l.ends_with("/plain")).expect("the wrapper is missing from the image");
+    assert!(wrapper.starts_with("-rwx"), "{}", wrapper);
+    assert!(listing.contains("/.plain-original"), "{}", listing);
+}
+
+#[test]
+fn unknown_flags_are_a_usage_error() {
+    let out = sharedpacker(&["--bogus"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("bogus"));
+}
+
+#[test]
+fn interrupting_a_forced_pack_keeps_the_previous_output() {
+    if !tools_available() {
+        return;
+    }
+    use std::os::unix::fs::PermissionsExt;
+    let tmp = TempDir::new("interrupt-force");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+    assert_success(&sharedpacker(&[sample.to_str().unwrap(), "-o", output.to_str().unwrap()]));
+    let before = std::fs::read(output.join("libone.so")).unwrap();
+
+    // a patchelf slow enough to interrupt the copy with
+    let slow = tmp.path.join("slow-patchelf");
+    std::fs::write(&slow, "#!/bin/sh\nsleep 0.5\nexec patchelf \"$@\"\n").unwrap();
+    std::fs::set_permissions(&slow, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sharedpacker"))
+        .args([sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--force",
+            "--patch-strategy", "temp-then-move", "--patchelf-path", slow.to_str().unwrap()])
+        .stderr(std::process::Stdio::null())
+        .spawn().unwrap();
+    let staging = tmp.path.join(format!(".out.building-{}", child.id()));
+    let started = std::time::Instant::now();
+    while !staging.exists() && started.elapsed() < std::time::Duration::from_secs(20) {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert!(staging.exists(), "the forced pack never started building");
+    assert!(Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap().success());
+    assert_eq!(child.wait().unwrap().code(), Some(130));
+
+    assert_eq!(std::fs::read(output.join("libone.so")).unwrap(), before);
+    let leftovers: Vec<_> = std::fs::read_dir(&tmp.path).unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with('.'))
+        .collect();
+    assert!(leftovers.is_empty(), "left behind {:?}", leftovers);
+}
+
+#[test]
+fn upx_compresses_only_after_check_closure() {
+    if !tools_available() {
+        return;
+    }
+    use std::os::unix::fs::PermissionsExt;
+    let tmp = TempDir::new("upx-check-closure");
+    let sample = build_sample(&tmp.path);
+    let output = tmp.path.join("out");
+    // like upx, leaves an elf that patchelf can no longer read the needed libs of
+    let bin = tmp.path.join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    std::fs::write(bin.join("upx"),
+        "#!/bin/sh\n[ \"$1\" = --version ] && exit 0\nhead -c 64 \"$2\" > \"$2.upx\" && mv \"$2.upx\" \"$2\"\n").unwrap();
+    std::fs::set_permissions(bin.join("upx"), std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+
+    let out = Command::new(env!("CARGO_BIN_EXE_sharedpacker"))
+        .args([sample.to_str().unwrap(), "-o", output.to_str().unwrap(), "--upx", "--check-closure"])
+        .env("PATH", &path)
+        .output().unwrap();
+    assert_success(&out);
+    for name in ["sample", "libone.so", "libtwo.so"] {
+        assert_eq!(std::fs::metadata(output.join(name)).unwrap().len(), 64, "{} was not compressed", name);
+    }
+    // the loader is left as it is
+    let loader = std::fs::read_dir(&output).unwrap().map(|e| e.unwrap().path())
+        .find(|p| p.file_name().unwrap().to_string_lossy().starts_with("ld-")).unwrap();
+    assert!(std::fs::metadata(loader).unwrap().len() > 64);
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::{TempDir, build_sample, tools_available};
+use sharedpacker::Error;
+use sharedpacker::patch::{PatchOps, apply_patch_ops, combine_rpath, origin_subdir_entries, verify_patch_ops};
+use sharedpacker::tools::ToolConfig;
+
+#[test]
+fn appended_rpath_keeps_existing_entries_after_origin() {
+    assert_eq!(combine_rpath("$ORIGIN", ""), "$ORIGIN");
+    assert_eq!(combine_rpath("$ORIGIN", "/opt/app/plugins:/opt/app/lib"), "$ORIGIN:/opt/app/plugins:/opt/app/lib");
+}
+
+#[test]
+fn appended_rpath_does_not_repeat_origin_when_repacking() {
+    assert_eq!(combine_rpath("$ORIGIN", "$ORIGIN:/opt/app/plugins"), "$ORIGIN:/opt/app/plugins");
+}
+
+#[test]
+fn finds_origin_entries_that_point_at_other_directories() {
+    assert_eq!(origin_subdir_entries("$ORIGIN/../lib:/usr/lib:${ORIGIN}/plugins"), ["$ORIGIN/../lib", "${ORIGIN}/plugins"]);
+    assert!(origin_subdir_entries("$ORIGIN:$ORIGIN/:${ORIGIN}/.:.:").is_empty());
+}
+
+/// a patchelf that logs its args and only has room for the program headers with --page-size room_with
+fn fake_patchelf(dir: &std::path::Path, room_with: &str) -> ToolConfig {
+    use std::os::unix::fs::PermissionsExt;
+    let script = dir.join("patchelf");
+    std::fs::write(&script, format!("#!/bin/sh\necho \"$@\" >> {}/log\n\
+        case \"$*\" in *\"--page-size {}\"*) exit 0;; esac\n\
+        echo 'cannot find room for the program headers' >&2\nexit 1\n", dir.display(), room_with)).unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    ToolConfig { patchelf: script.to_string_lossy().into(), ..Default::default() }
+}
+
+#[test]
+fn retries_with_a_page_size_when_there_is_no_room_for_the_program_headers() {
+    let tmp = TempDir::new("patch-page-size");
+    let tools = fake_patchelf(&tmp.path, "16384");
+    let ops = PatchOps { set_rpath: Some("$ORIGIN".into()), ..Default::default() };
+    apply_patch_ops(&tools, &ops, &tmp.path.join("big")).unwrap();
+    let log = std::fs::read_to_string(tmp.path.join("log")).unwrap();
+    assert_eq!(log.lines().collect::<Vec<_>>(), [
+        format!("--set-rpath $ORIGIN {}/big", tmp.path.display()),
+        format!("--page-size 4096 --set-rpath $ORIGIN {}/big", tmp.path.display()),
+        format!("--page-size 16384 --set-rpath $ORIGIN {}/big", tmp.path.display()),
+    ]);
+}
+
+#[test]
+fn says_which_page_sizes_were_tried_when_none_of_them_has_room() {
+    let tmp = TempDir::new("patch-no-room");
+    let tools = fake_patchelf(&tmp.path, "none");
+    let ops = PatchOps { set_rpath: Some("$ORIGIN".into()), ..Default::default() };
+    let err = apply_patch_ops(&tools, &ops, &tmp.path.join("big")).unwrap_err().to_string();
+    assert!(err.contains("even with --page-size 4096, 16384, 65536"), "{}", err);
+    assert_eq!(std::fs::read_to_string(tmp.path.join("log")).unwrap().lines().count(), 4);
+}
+
+#[test]
+fn verifying_an_interpreter_that_is_not_there_fails() {
+    if !tools_available() {
+        return;
+    }
+    let tmp = TempDir::new("verify-no-interpreter");
+    build_sample(&tmp.path);
+    // a shared lib has no interpreter to read back, even though ldd reports a loader for it
+    let ops = PatchOps { set_interpreter: Some("./ld-linux-x86-64.so.2".into()), ..Default::default() };
+    let err = verify_patch_ops(&ToolConfig::default(), &ops, &tmp.path.join("libone.so")).unwrap_err();
+    assert!(matches!(err, Error::Patch(_)), "{}", err);
+    assert!(err.to_string().contains("has no interpreter"), "{}", err);
+}
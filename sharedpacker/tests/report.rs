@@ -0,0 +1,30 @@
+use sharedpacker::report::{LibRecord, LibStatus, Summary, make_report_json, make_summary_json};
+
+#[test]
+fn writes_a_record_per_lib_with_a_null_for_an_unknown_path() {
+    let records = vec![
+        LibRecord { name: "libone.so".into(), path: Some("/lib/libone.so".into()), status: LibStatus::Copied },
+        LibRecord { name: "libGL.so.1".into(), path: None, status: LibStatus::Excluded },
+        LibRecord { name: "libz.so.1".into(), path: Some("/lib/libz.so.1".into()), status: LibStatus::InBase },
+    ];
+    let value: serde_json::Value = serde_json::from_str(&make_report_json(&records)).unwrap();
+    assert_eq!(value, serde_json::json!([
+        { "name": "libone.so", "path": "/lib/libone.so", "status": "copied" },
+        { "name": "libGL.so.1", "path": null, "status": "excluded" },
+        { "name": "libz.so.1", "path": "/lib/libz.so.1", "status": "in-base" },
+    ]));
+    assert_eq!(make_report_json(&[]), "[]\n");
+}
+
+#[test]
+fn the_summary_is_one_json_object() {
+    let summary = Summary { libraries: 4, bytes: 2048, deduplicated: 1, elapsed_ms: 35, ..Default::default() };
+    let line = make_summary_json(&summary);
+    assert!(!line.contains('\n'));
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(value["libraries"], 4);
+    assert_eq!(value["bytes"], 2048);
+    assert_eq!(value["deduplicated"], 1);
+    assert_eq!(value["failed"], 0);
+    assert_eq!(value["elapsed_ms"], 35);
+}
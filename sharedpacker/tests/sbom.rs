@@ -0,0 +1,32 @@
+mod common;
+
+use common::TempDir;
+use sharedpacker::sbom::{path_relative_to_base, read_target_inventory};
+use std::path::Path;
+
+#[test]
+fn records_paths_under_the_base_relative_to_it() {
+    let tmp = TempDir::new("sbom-base");
+    let base = std::fs::canonicalize(&tmp.path).unwrap();
+    std::fs::create_dir(base.join("lib")).unwrap();
+    std::fs::write(base.join("lib/libone.so"), "").unwrap();
+
+    assert_eq!(path_relative_to_base(&base.join("lib/libone.so"), Some(&base)), "lib/libone.so");
+    assert_eq!(path_relative_to_base(Path::new("/usr/lib/libc.so.6"), Some(&base)), "/usr/lib/libc.so.6");
+    assert_eq!(path_relative_to_base(&base.join("lib/libone.so"), None), base.join("lib/libone.so").to_string_lossy());
+}
+
+#[test]
+fn reads_a_target_inventory_with_optional_versions() {
+    let tmp = TempDir::new("sbom-inventory");
+    let inventory = tmp.path.join("inventory");
+    std::fs::write(&inventory, "# the libs of the target\nlibc.so.6\n\n  libssl.so.3 3.0.2\n").unwrap();
+
+    let libs = read_target_inventory(&inventory).unwrap();
+    assert_eq!(libs.len(), 2);
+    assert_eq!(libs["libc.so.6"], None);
+    assert_eq!(libs["libssl.so.3"].as_deref(), Some("3.0.2"));
+
+    std::fs::write(&inventory, "libssl.so.3 3.0.2 extra\n").unwrap();
+    assert!(read_target_inventory(&inventory).is_err());
+}
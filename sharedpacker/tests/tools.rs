@@ -0,0 +1,9 @@
+use sharedpacker::tools::is_retryable_message;
+
+#[test]
+fn only_transient_failures_are_retried() {
+    assert!(is_retryable_message("patchelf: open: Text file busy"));
+    assert!(is_retryable_message("ldd: fork: Resource temporarily unavailable"));
+    assert!(!is_retryable_message("ldd: ./missing: No such file or directory"));
+    assert!(!is_retryable_message("patchelf: not an ELF executable"));
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::TempDir;
+use sharedpacker::wrapper::{WrapperOptions, make_shell_script_wrapper};
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+
+#[test]
+fn flat_wrapper_uses_the_script_directory() {
+    let script = make_shell_script_wrapper(".app-original", "ld-linux-x86-64.so.2", &WrapperOptions::default());
+    assert!(script.starts_with("#!/usr/bin/env bash\n"));
+    assert!(script.ends_with(
+        "\"$SCRIPTPATH/ld-linux-x86-64.so.2\" --library-path \"$SCRIPTPATH\" \"$SCRIPTPATH/.app-original\" \"$@\""));
+}
+
+#[test]
+fn lib_subdir_wrapper_points_the_loader_and_library_path_into_it() {
+    let opts = WrapperOptions { lib_dir: "lib/".into(), ..Default::default() };
+    let script = make_shell_script_wrapper(".app-original", "ld-linux-x86-64.so.2", &opts);
+    assert!(script.ends_with(
+        "\"$SCRIPTPATH/lib/ld-linux-x86-64.so.2\" --library-path \"$SCRIPTPATH/lib\" \"$SCRIPTPATH/.app-original\" \"$@\""));
+}
+
+#[test]
+fn exports_env_vars_before_launching() {
+    let env = vec![
+        ("LD_BIND_NOW".to_string(), "1".to_string()),
+        ("GREETING".to_string(), "say \"hi\" to $USER".to_string()),
+    ];
+    let script = make_shell_script_wrapper(".app-original", "ld.so", &WrapperOptions { env, ..Default::default() });
+    let bind_now = script.find("export LD_BIND_NOW=\"1\"\n").expect("missing LD_BIND_NOW export");
+    let launch = script.find("\"$SCRIPTPATH/ld.so\"").unwrap();
+    assert!(bind_now < launch);
+    assert!(script.contains("export GREETING=\"say \\\"hi\\\" to \\$USER\"\n"));
+}
+
+#[test]
+fn escapes_names_that_are_special_inside_double_quotes() {
+    let script = make_shell_script_wrapper(".my \"app\"$1-original", "ld.so", &WrapperOptions::default());
+    assert!(script.ends_with("\"$SCRIPTPATH/.my \\\"app\\\"\\$1-original\" \"$@\""));
+}
+
+#[test]
+fn passthrough_scrubs_everything_but_the_named_variables() {
+    let tmp = TempDir::new("wrapper-passthrough");
+    // stands in for the loader: prints the env of the "executable" it is given, after the args
+    let loader = tmp.path.join("ld.so");
+    std::fs::write(&loader, "#!/bin/sh\nshift 3\necho \"$@\"\nenv\n").unwrap();
+    std::fs::set_permissions(&loader, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let opts = WrapperOptions {
+        env: vec![("FROM_WRAPPER".to_string(), "yes".to_string())],
+        passthrough: vec!["KEEP_ME".to_string(), "UNSET_ONE".to_string()],
+        ..Default::default()
+    };
+    let script = make_shell_script_wrapper(".app-original", "ld.so", &opts);
+    let wrapper = tmp.path.join("app");
+    std::fs::write(&wrapper, script).unwrap();
+
+    let out = Command::new("bash").arg(&wrapper).args(["one arg", "two"])
+        .env("KEEP_ME", "kept").env("DROP_ME", "dropped").env_remove("UNSET_ONE")
+        .output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let mut lines = stdout.lines();
+    assert_eq!(lines.next(), Some("one arg two"));
+    let env: Vec<&str> = lines.collect();
+    assert!(env.contains(&"KEEP_ME=kept"), "{:?}", env);
+    assert!(env.contains(&"FROM_WRAPPER=yes"), "{:?}", env);
+    assert!(!env.iter().any(|l| l.starts_with("DROP_ME=") || l.starts_with("UNSET_ONE=")), "{:?}", env);
+}